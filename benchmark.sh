@@ -1,2 +1 @@
-cargo run -- ./tests/mandelbrot.input -o mandelbrot
-time ./mandelbrot
+cargo run --release -- bench ./tests/mandelbrot.input "$@"
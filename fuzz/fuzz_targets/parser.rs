@@ -0,0 +1,34 @@
+#![no_main]
+
+use brainfc::{Dialect, Extension};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte picks the dialect and extensions, the rest is the source
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, source)) = data.split_first() else {
+        return;
+    };
+    let dialect = match selector % 4 {
+        0 => Dialect::Brainfuck,
+        1 => Dialect::Pbrain,
+        2 => Dialect::Brainfork,
+        _ => Dialect::Ook,
+    };
+    let extensions: Vec<Extension> = [Extension::DebugDump, Extension::InlineInput]
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selector & (4 << i) != 0)
+        .map(|(_, extension)| extension)
+        .collect();
+
+    let source = String::from_utf8_lossy(source);
+    let (program, _) = match extensions.contains(&Extension::InlineInput) {
+        true => brainfc::lexer::split_inline_input(&source),
+        false => (source.as_ref(), None),
+    };
+    if let Ok(parsed) =
+        brainfc::parser::parse(brainfc::lexer::parse_with(program, &dialect, &extensions))
+    {
+        brainfc::optimize(parsed);
+    }
+});
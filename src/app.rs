@@ -1,20 +1,307 @@
-use clap::Parser;
+use brainfc::parser::Program;
+use brainfc::preprocessor::SourceMap;
+use brainfc::{
+    Backend, BrainfcError, CompileOptions, CrateType, Dialect, ExitCode, Extension, IoMode,
+    OptLevel, WasmAbi,
+};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    #[arg(value_name = "path", value_hint = clap::ValueHint::DirPath)]
-    pub input: std::path::PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(value_name = "path", value_hint = clap::ValueHint::DirPath, required = true)]
+    pub input: Option<std::path::PathBuf>,
+
+    /// brainfuck, pbrain, brainfork, ook, blub or a .toml/.json token mapping [default: by file extension, else brainfuck]
+    #[arg(long, value_name = "DIALECT")]
+    pub dialect: Option<String>,
+
+    /// Language extension to enable, may be repeated
+    #[arg(long = "ext", value_name = "EXT", value_enum, value_delimiter = ',')]
+    pub extensions: Vec<Extension>,
 
     #[arg(short, long)]
     pub output: Option<std::path::PathBuf>,
 
+    #[arg(long, value_enum, default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    #[arg(long, value_enum, default_value_t = CrateType::Bin)]
+    pub crate_type: CrateType,
+
+    /// I/O of the generated code [default: stdio for bin, buffer for cdylib]
+    #[arg(long, value_enum)]
+    pub io: Option<IoMode>,
+
+    #[arg(long, value_enum, default_value_t = ExitCode::Zero)]
+    pub exit_code: ExitCode,
+
+    /// How much LLVM optimizes the machine code, ignored by the other backends
+    #[arg(long, value_enum, default_value_t = OptLevel::Default)]
+    pub opt_level: OptLevel,
+
+    /// Print the tape pointer and the cells around it to stderr when the program finishes
+    #[arg(long, default_value_t = false)]
+    pub dump_tape_at_exit: bool,
+
+    /// Count executions of every block and loop and print a report to stderr when the program finishes
+    #[arg(long, default_value_t = false)]
+    pub profile: bool,
+
+    #[arg(long, value_enum, default_value_t = WasmAbi::Env)]
+    pub wasm_abi: WasmAbi,
+
+    /// Emit DWARF debug info mapping the executable back to the source file
+    #[arg(short = 'g', default_value_t = false)]
+    pub debug_info: bool,
+
+    /// Linker or compiler driver used for linking [default: first of cc, clang, gcc, ld.lld]
+    #[arg(long)]
+    pub linker: Option<std::path::PathBuf>,
+
+    /// Extra argument passed to the linker, may be repeated
+    #[arg(long = "link-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub link_args: Vec<String>,
+
+    /// Produce a statically linked executable
+    #[arg(long = "static", default_value_t = false)]
+    pub static_linking: bool,
+
+    /// Use raw Linux syscalls instead of libc and link a static executable without the C runtime
+    #[arg(long, default_value_t = false)]
+    pub freestanding: bool,
+
+    /// Stop after writing the object file
+    #[arg(long, default_value_t = false)]
+    pub no_link: bool,
+
+    /// Print the program after expanding macros and includes of --ext macros
+    #[arg(long, default_value_t = false)]
+    pub show_expanded: bool,
+
     #[arg(long, default_value_t = false)]
     pub show_parsed: bool,
 
     #[arg(long, default_value_t = false)]
     pub show_optimized: bool,
 
+    #[cfg(feature = "llvm")]
     #[arg(long, default_value_t = false)]
     pub show_llvm_ir: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub show_asm: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub show_wat: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Step through a program in an interactive debugger
+    Debug(DebugArgs),
+    /// Generate a short brainfuck program that prints the given text
+    GenPrint(GenPrintArgs),
+    /// Compile every `*.b` program in a directory and compare its output with `*.out`
+    Test(TestArgs),
+    /// Time programs compiled with different backends and optimizations
+    Bench(BenchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Programs to benchmark, `name.in` is fed to `name.b` if it exists
+    #[arg(value_name = "path", required = true, value_hint = clap::ValueHint::FilePath)]
+    pub paths: Vec<std::path::PathBuf>,
+
+    /// Backend to benchmark, may be repeated [default: every backend producing executables]
+    #[arg(
+        long = "backend",
+        value_name = "BACKEND",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub backends: Vec<Backend>,
+
+    /// LLVM optimization level to benchmark, may be repeated [default: none, default, aggressive]
+    #[arg(
+        long = "opt-level",
+        value_name = "LEVEL",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub opt_levels: Vec<OptLevel>,
+
+    /// Timed runs of every configuration
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub runs: u32,
+
+    /// Untimed runs before the timed ones
+    #[arg(long, default_value_t = 1)]
+    pub warmup: u32,
+
+    /// Print the results as JSON instead of a table
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+
+    /// brainfuck, pbrain, brainfork, ook, blub or a .toml/.json token mapping [default: by file extension, else brainfuck]
+    #[arg(long, value_name = "DIALECT")]
+    pub dialect: Option<String>,
+
+    /// Language extension to enable, may be repeated
+    #[arg(long = "ext", value_name = "EXT", value_enum, value_delimiter = ',')]
+    pub extensions: Vec<Extension>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TestArgs {
+    /// Programs, or directories of `name.b` programs, with `name.in` as input and `name.out` as expected output
+    #[arg(value_name = "path", required = true, value_hint = clap::ValueHint::AnyPath)]
+    pub paths: Vec<std::path::PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    /// Seconds a program may run before it fails
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub timeout: u64,
+
+    /// brainfuck, pbrain, brainfork, ook, blub or a .toml/.json token mapping [default: brainfuck]
+    #[arg(long, value_name = "DIALECT")]
+    pub dialect: Option<String>,
+
+    /// Language extension to enable, may be repeated
+    #[arg(long = "ext", value_name = "EXT", value_enum, value_delimiter = ',')]
+    pub extensions: Vec<Extension>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GenPrintArgs {
+    /// Text to print, taken as the bytes the shell passes
+    #[arg(required_unless_present = "file")]
+    pub text: Option<std::ffi::OsString>,
+
+    /// Print the bytes of this file instead, `-` for stdin
+    #[arg(short, long, conflicts_with = "text")]
+    pub file: Option<std::path::PathBuf>,
+
+    /// Append a newline to the text
+    #[arg(short, long, default_value_t = false)]
+    pub newline: bool,
+
+    /// Write the program to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DebugArgs {
+    #[arg(value_name = "path", value_hint = clap::ValueHint::FilePath)]
+    pub path: std::path::PathBuf,
+
+    /// File the program reads with `,` [default: inline input or no input]
+    #[arg(long)]
+    pub input: Option<std::path::PathBuf>,
+
+    /// brainfuck, pbrain, brainfork, ook, blub or a .toml/.json token mapping [default: by file extension, else brainfuck]
+    #[arg(long, value_name = "DIALECT")]
+    pub dialect: Option<String>,
+
+    /// Language extension to enable, may be repeated
+    #[arg(long = "ext", value_name = "EXT", value_enum, value_delimiter = ',')]
+    pub extensions: Vec<Extension>,
+}
+
+/// Reads the program at `path`, expanding it if `Extension::Macros` is
+/// enabled. Positions in errors about the returned text are mapped back to
+/// the files it came from by the returned `SourceMap`.
+pub fn read_source(
+    path: &std::path::Path,
+    extensions: &[Extension],
+) -> Result<(String, Option<SourceMap>), BrainfcError> {
+    if extensions.contains(&Extension::Macros) {
+        let expansion = brainfc::preprocessor::expand(path)?;
+        return Ok((expansion.source, Some(expansion.map)));
+    }
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?;
+    Ok((source, None))
+}
+
+/// Parses the text returned by `read_source`, with `brainfc::lang` for
+/// `*.bfl` files and as `--dialect` otherwise. Returns the program and the
+/// input embedded in the source, if any.
+pub fn parse_source<'a>(
+    path: &std::path::Path,
+    source: &'a str,
+    dialect: Option<&str>,
+    extensions: &[Extension],
+) -> Result<(Program, Option<&'a str>), BrainfcError> {
+    if brainfc::lang::is_source(path) {
+        return Ok((brainfc::lang::compile(source)?, None));
+    }
+    let dialect = Dialect::resolve(dialect, path)?;
+    let (program, inline_input) = split_source(source, &dialect, extensions)?;
+    let parsed = brainfc::parse(brainfc::lex_with(program, &dialect, extensions))?;
+    Ok((parsed, inline_input))
+}
+
+/// Splits off the input after `!` if `Extension::InlineInput` is enabled
+fn split_source<'a>(
+    source: &'a str,
+    dialect: &Dialect,
+    extensions: &[Extension],
+) -> Result<(&'a str, Option<&'a str>), BrainfcError> {
+    if !extensions.contains(&Extension::InlineInput) {
+        return Ok((source, None));
+    }
+    if dialect.token_table().is_some() {
+        return Err(BrainfcError::Parse(
+            "--ext inline-input does not apply to token dialects".to_string(),
+        ));
+    }
+    Ok(brainfc::lexer::split_inline_input(source))
+}
+
+impl Args {
+    pub fn compile_options(&self) -> CompileOptions {
+        let show_ir = match self.backend {
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => self.show_llvm_ir,
+            Backend::Asm => self.show_asm,
+            Backend::Wasm => self.show_wat,
+        };
+
+        let mut options = CompileOptions::new()
+            .backend(self.backend)
+            .crate_type(self.crate_type)
+            .exit_code(self.exit_code)
+            .opt_level(self.opt_level)
+            .dump_tape_at_exit(self.dump_tape_at_exit)
+            .profile(self.profile)
+            .wasm_abi(self.wasm_abi)
+            .show_ir(show_ir)
+            .static_linking(self.static_linking)
+            .freestanding(self.freestanding);
+        if let Some(io) = self.io {
+            options = options.io(io);
+        }
+        if let Some(input) = self.input.as_ref().filter(|_| self.debug_info) {
+            options = options.debug_info(input);
+        }
+        if let Some(output) = &self.output {
+            options = options.output(output);
+        }
+        if let Some(linker) = &self.linker {
+            options = options.linker(linker);
+        }
+        for arg in &self.link_args {
+            options = options.link_arg(arg);
+        }
+        options
+    }
 }
@@ -0,0 +1,610 @@
+use std::fmt::Write;
+
+use crate::cdylib::TAPE_SIZE;
+use crate::error::{BrainfcError, Result};
+use crate::freestanding::RUNTIME_ASM;
+use crate::lexer::{Op, Pos};
+use crate::options::{CompileOptions, CrateType, ExitCode, IoMode};
+use crate::parser::{Block, Program, Span};
+use crate::profile::{self, Site};
+
+// Callee-saved, so it survives calls into libc: address of the current cell
+const PTR: &str = "%rbx";
+
+// Buffer I/O state of `bf_run_tape`, see `create_run_tape_fn`
+const IN: &str = "%r12";
+const IN_END: &str = "%r13";
+const OUT: &str = "%r14";
+const OUT_END: &str = "%r15";
+const OUT_LEN: &str = "%rbp";
+const OUT_START: &str = "(%rsp)";
+const TRUNCATED: &str = "8(%rsp)";
+// First cell of the tape passed to `bf_run_tape`
+const TAPE_START: &str = "16(%rsp)";
+
+// Callback I/O state of `bf_run_tape`
+const READ_FN: &str = "%r12";
+const WRITE_FN: &str = "%r13";
+const CTX: &str = "%r14";
+
+struct AsmBuilder {
+    code: String,
+    label_id: u32,
+    crate_type: CrateType,
+    io: IoMode,
+    exit_code: ExitCode,
+    dump_tape_at_exit: bool,
+    uses_dump_tape: bool,
+    embedded_input: Option<Vec<u8>>,
+    debug_info: bool,
+    profile_sites: Option<Vec<Site>>,
+    next_site: usize,
+    /// Code of the pbrain procedures, emitted after `main`
+    procedures: String,
+    procedure_id: u32,
+    uses_procedures: bool,
+    uses_fork: bool,
+}
+
+impl AsmBuilder {
+    fn new(options: &CompileOptions) -> Self {
+        AsmBuilder {
+            code: String::new(),
+            label_id: 0,
+            crate_type: options.crate_type,
+            io: options.get_io(),
+            exit_code: options.exit_code,
+            dump_tape_at_exit: options.dump_tape_at_exit,
+            uses_dump_tape: options.dump_tape_at_exit,
+            embedded_input: options.embedded_input.clone(),
+            debug_info: options.debug_source.is_some(),
+            profile_sites: None,
+            next_site: 0,
+            procedures: String::new(),
+            procedure_id: 0,
+            uses_procedures: false,
+            uses_fork: false,
+        }
+    }
+
+    /// Offset of the counter of the next site, see `profile::sites`
+    fn next_site_offset(&mut self) -> Option<usize> {
+        self.profile_sites.as_ref()?;
+        self.next_site += 1;
+        Some((self.next_site - 1) * 8)
+    }
+
+    fn count_site(&mut self, offset: Option<usize>) {
+        if let Some(offset) = offset {
+            self.emit(format!("incq bf_profile_counts+{offset}(%rip)"));
+        }
+    }
+
+    fn create_profile_data(&mut self, sites: &[Site]) {
+        self.emit(".section .rodata");
+        self.emit(".p2align 2");
+        self.emit("bf_profile_sites:");
+        for site in sites {
+            let [line, col, kind] = site.record();
+            self.emit(format!(".long {line}, {col}, {kind}"));
+        }
+        self.emit(".bss");
+        self.emit(".p2align 3");
+        self.emit("bf_profile_counts:");
+        self.emit(format!(".zero {}", sites.len() * 8));
+    }
+
+    /// Makes the assembler generate DWARF line info for `source`, see
+    /// `set_location`
+    fn create_debug_file(&mut self, source: &std::path::Path) {
+        self.emit(format!(".file 1 {:?}", source.display().to_string()));
+    }
+
+    fn set_location(&mut self, pos: Pos) {
+        if self.debug_info {
+            self.emit(format!(".loc 1 {} {}", pos.line, pos.col));
+        }
+    }
+
+    fn next_label_id(&mut self) -> u32 {
+        self.label_id += 1;
+        self.label_id
+    }
+
+    fn emit(&mut self, line: impl AsRef<str>) {
+        let line = line.as_ref();
+        if line.ends_with(':') || line.starts_with('.') {
+            writeln!(self.code, "{line}").unwrap();
+        } else {
+            writeln!(self.code, "    {line}").unwrap();
+        }
+    }
+
+    fn create_main_fn(&mut self) {
+        self.emit(".text");
+        self.emit(".globl main");
+        self.emit(".type main, @function");
+        self.emit("main:");
+        // Also realigns the stack to 16 bytes for calls into libc
+        self.emit(format!("push {PTR}"));
+        self.emit(format!("lea tape(%rip), {PTR}"));
+    }
+
+    /// `int bf_run_tape(uint8_t *tape, ...)`, followed by the parameters the
+    /// I/O mode needs, see `cdylib::header`
+    fn create_run_tape_fn(&mut self) {
+        self.emit(".text");
+        self.emit(".globl bf_run_tape");
+        self.emit(".type bf_run_tape, @function");
+        self.emit("bf_run_tape:");
+        for reg in ["%rbx", "%rbp", "%r12", "%r13", "%r14", "%r15"] {
+            self.emit(format!("push {reg}"));
+        }
+        self.emit("sub $24, %rsp");
+        self.emit(format!("mov %rdi, {PTR}"));
+        self.emit(format!("mov %rdi, {TAPE_START}"));
+        match self.io {
+            // Rewinds the inline input, so each call reads it from the start
+            IoMode::Stdio if self.embedded_input.is_some() => {
+                self.emit("movq $0, bf_input_pos(%rip)");
+            }
+            IoMode::Stdio => {}
+            IoMode::Buffer => {
+                self.emit(format!("mov %rsi, {IN}"));
+                self.emit(format!("lea (%rsi,%rdx), {IN_END}"));
+                self.emit(format!("mov %rcx, {OUT}"));
+                self.emit(format!("lea (%rcx,%r8), {OUT_END}"));
+                self.emit(format!("mov %r9, {OUT_LEN}"));
+                self.emit(format!("mov %rcx, {OUT_START}"));
+                self.emit(format!("movl $0, {TRUNCATED}"));
+            }
+            IoMode::Callback => {
+                self.emit(format!("mov %rsi, {READ_FN}"));
+                self.emit(format!("mov %rdx, {WRITE_FN}"));
+                self.emit(format!("mov %rcx, {CTX}"));
+            }
+        }
+    }
+
+    /// `int bf_run(...)` with the parameters of `bf_run_tape` except the
+    /// tape, calls `bf_run_tape` with a zeroed tape on the stack
+    fn create_run_fn(&mut self) {
+        // Keeps the stack 16-byte aligned at the call
+        let frame_size = TAPE_SIZE + 8 + (16 - TAPE_SIZE % 16) % 16;
+
+        self.emit(".globl bf_run");
+        self.emit(".type bf_run, @function");
+        self.emit("bf_run:");
+        self.emit(format!("sub ${frame_size}, %rsp"));
+        self.emit("mov %rdi, %r10");
+        self.emit("mov %rcx, %r11");
+        self.emit("mov %rsp, %rdi");
+        self.emit(format!("mov ${TAPE_SIZE}, %ecx"));
+        self.emit("xor %eax, %eax");
+        self.emit("rep stosb");
+        self.emit("mov %r8, %r9");
+        self.emit("mov %r11, %r8");
+        self.emit("mov %rdx, %rcx");
+        self.emit("mov %rsi, %rdx");
+        self.emit("mov %r10, %rsi");
+        self.emit("mov %rsp, %rdi");
+        self.emit("call bf_run_tape@PLT");
+        self.emit(format!("add ${frame_size}, %rsp"));
+        self.emit("ret");
+        self.emit(".size bf_run, .-bf_run");
+    }
+
+    /// Calls `bf_dump_tape` with the current cell and the start of the tape
+    fn build_dump_tape(&mut self) {
+        self.uses_dump_tape = true;
+        self.emit(format!("mov {PTR}, %rdi"));
+        match self.crate_type {
+            CrateType::Bin => self.emit("lea tape(%rip), %rsi"),
+            CrateType::Cdylib => self.emit(format!("mov {TAPE_START}, %rsi")),
+        }
+        self.emit("call bf_dump_tape");
+    }
+
+    /// Prints the position of the cell pointer in `%rdi` relative to the
+    /// tape starting at `%rsi` and up to 8 cells on either side of it to
+    /// stderr
+    fn create_dump_tape_fn(&mut self) {
+        self.emit(".section .rodata");
+        self.emit(".Ldump_pos:");
+        self.emit(".asciz \"tape pointer: %ld\\n\"");
+        self.emit(".Ldump_range:");
+        self.emit(".asciz \"cells %ld..%ld:\"");
+        self.emit(".Ldump_cell:");
+        self.emit(".asciz \" %d\"");
+        self.emit(".Ldump_end:");
+        self.emit(".asciz \"\\n\"");
+
+        self.emit(".text");
+        self.emit(".type bf_dump_tape, @function");
+        self.emit("bf_dump_tape:");
+        // Three pushes realign the stack for the calls to dprintf
+        self.emit("push %rbx");
+        self.emit("push %r12");
+        self.emit("push %r13");
+        self.emit("mov %rsi, %r13");
+        self.emit("mov %rdi, %rbx");
+        self.emit("sub %r13, %rbx");
+        self.emit("mov $2, %edi");
+        self.emit("lea .Ldump_pos(%rip), %rsi");
+        self.emit("mov %rbx, %rdx");
+        self.emit("xor %eax, %eax");
+        self.emit("call dprintf@PLT");
+
+        // Window [max(pos - 8, 0), min(pos + 9, TAPE_SIZE)) in %r12..%rbx
+        self.emit("lea -8(%rbx), %r12");
+        self.emit("xor %eax, %eax");
+        self.emit("test %r12, %r12");
+        self.emit("cmovs %rax, %r12");
+        self.emit("lea 9(%rbx), %rbx");
+        self.emit(format!("mov ${TAPE_SIZE}, %eax"));
+        self.emit("cmp %rax, %rbx");
+        self.emit("cmovg %rax, %rbx");
+        self.emit("mov $2, %edi");
+        self.emit("lea .Ldump_range(%rip), %rsi");
+        self.emit("mov %r12, %rdx");
+        self.emit("mov %rbx, %rcx");
+        self.emit("xor %eax, %eax");
+        self.emit("call dprintf@PLT");
+
+        self.emit(".Ldump_loop:");
+        self.emit("cmp %rbx, %r12");
+        self.emit("jge .Ldump_done");
+        self.emit("mov $2, %edi");
+        self.emit("lea .Ldump_cell(%rip), %rsi");
+        self.emit("movzbl (%r13,%r12), %edx");
+        self.emit("xor %eax, %eax");
+        self.emit("call dprintf@PLT");
+        self.emit("inc %r12");
+        self.emit("jmp .Ldump_loop");
+        self.emit(".Ldump_done:");
+        self.emit("mov $2, %edi");
+        self.emit("lea .Ldump_end(%rip), %rsi");
+        self.emit("xor %eax, %eax");
+        self.emit("call dprintf@PLT");
+
+        self.emit("pop %r13");
+        self.emit("pop %r12");
+        self.emit("pop %rbx");
+        self.emit("ret");
+        self.emit(".size bf_dump_tape, .-bf_dump_tape");
+    }
+
+    fn finalize(&mut self) {
+        match self.crate_type {
+            CrateType::Bin => {
+                if self.uses_fork {
+                    // Finish only once all children have
+                    let id = self.next_label_id();
+                    self.emit(format!(".Lwait_{id}:"));
+                    self.emit("xor %edi, %edi");
+                    self.emit("call wait@PLT");
+                    self.emit("test %eax, %eax");
+                    self.emit(format!("jg .Lwait_{id}"));
+                }
+                if let Some(sites) = &self.profile_sites {
+                    let n = sites.len();
+                    self.emit("lea bf_profile_sites(%rip), %rdi");
+                    self.emit("lea bf_profile_counts(%rip), %rsi");
+                    self.emit(format!("mov ${n}, %edx"));
+                    self.emit("call bf_profile_report");
+                }
+                if self.dump_tape_at_exit {
+                    self.build_dump_tape();
+                }
+                match self.exit_code {
+                    ExitCode::Zero => self.emit("xor %eax, %eax"),
+                    ExitCode::Cell => self.emit(format!("movzbl ({PTR}), %eax")),
+                }
+                self.emit(format!("pop {PTR}"));
+                self.emit("ret");
+                self.emit(".size main, .-main");
+                let procedures = std::mem::take(&mut self.procedures);
+                self.code.push_str(&procedures);
+
+                if let Some(sites) = self.profile_sites.take() {
+                    self.create_profile_data(&sites);
+                    self.code.push_str(profile::RUNTIME_ASM);
+                }
+
+                self.emit(".bss");
+                if self.uses_procedures {
+                    self.emit(".p2align 3");
+                    self.emit("bf_procs:");
+                    self.emit(".zero 2048");
+                }
+                self.emit("tape:");
+                self.emit(format!(".zero {TAPE_SIZE}"));
+            }
+            CrateType::Cdylib => {
+                if self.io == IoMode::Buffer {
+                    self.emit(format!("mov {OUT}, %rax"));
+                    self.emit(format!("sub {OUT_START}, %rax"));
+                    self.emit(format!("mov %rax, ({OUT_LEN})"));
+                    self.emit(format!("mov {TRUNCATED}, %eax"));
+                } else {
+                    self.emit("xor %eax, %eax");
+                }
+                self.emit("add $24, %rsp");
+                for reg in ["%r15", "%r14", "%r13", "%r12", "%rbp", "%rbx"] {
+                    self.emit(format!("pop {reg}"));
+                }
+                self.emit("ret");
+                self.emit(".size bf_run_tape, .-bf_run_tape");
+
+                self.create_run_fn();
+            }
+        }
+        if self.uses_dump_tape {
+            self.create_dump_tape_fn();
+        }
+        if let Some(input) = self.embedded_input.take() {
+            self.create_embedded_input(&input);
+        }
+        self.emit(".section .note.GNU-stack,\"\",@progbits");
+    }
+
+    /// `bf_input`, the bytes after `!`, and `bf_input_pos`, the index of the
+    /// next one `,` reads
+    fn create_embedded_input(&mut self, input: &[u8]) {
+        self.emit(".section .rodata");
+        self.emit("bf_input:");
+        for chunk in input.chunks(16) {
+            let bytes: Vec<String> = chunk.iter().map(|byte| byte.to_string()).collect();
+            self.emit(format!(".byte {}", bytes.join(", ")));
+        }
+        self.emit(".bss");
+        self.emit(".p2align 3");
+        self.emit("bf_input_pos:");
+        self.emit(".zero 8");
+    }
+
+    fn build_read(&mut self) {
+        match self.io {
+            IoMode::Stdio if self.embedded_input.is_some() => {
+                let len = self.embedded_input.as_ref().unwrap().len();
+                let id = self.next_label_id();
+                self.emit("mov $-1, %eax");
+                self.emit("mov bf_input_pos(%rip), %rcx");
+                self.emit(format!("cmp ${len}, %rcx"));
+                self.emit(format!("jae .Lread_{id}"));
+                self.emit("lea bf_input(%rip), %rdx");
+                self.emit("movzbl (%rdx,%rcx), %eax");
+                self.emit("inc %rcx");
+                self.emit("mov %rcx, bf_input_pos(%rip)");
+                self.emit(format!(".Lread_{id}:"));
+            }
+            IoMode::Stdio => {
+                self.emit("call getchar@PLT");
+            }
+            IoMode::Callback => {
+                self.emit(format!("mov {CTX}, %rdi"));
+                self.emit(format!("call *{READ_FN}"));
+            }
+            IoMode::Buffer => {
+                let id = self.next_label_id();
+                self.emit("mov $-1, %eax");
+                self.emit(format!("cmp {IN_END}, {IN}"));
+                self.emit(format!("jae .Lread_{id}"));
+                self.emit(format!("movzbl ({IN}), %eax"));
+                self.emit(format!("inc {IN}"));
+                self.emit(format!(".Lread_{id}:"));
+            }
+        }
+        self.emit(format!("mov %al, ({PTR})"));
+    }
+
+    fn build_write(&mut self) {
+        match self.io {
+            IoMode::Stdio => {
+                self.emit(format!("movzbl ({PTR}), %edi"));
+                self.emit("call putchar@PLT");
+            }
+            IoMode::Callback => {
+                self.emit(format!("mov {CTX}, %rdi"));
+                self.emit(format!("movzbl ({PTR}), %esi"));
+                self.emit(format!("call *{WRITE_FN}"));
+            }
+            IoMode::Buffer => {
+                let id = self.next_label_id();
+                self.emit(format!("cmp {OUT_END}, {OUT}"));
+                self.emit(format!("jae .Lfull_{id}"));
+                self.emit(format!("movzbl ({PTR}), %eax"));
+                self.emit(format!("mov %al, ({OUT})"));
+                self.emit(format!("inc {OUT}"));
+                self.emit(format!("jmp .Lwritten_{id}"));
+                self.emit(format!(".Lfull_{id}:"));
+                self.emit(format!("movl $1, {TRUNCATED}"));
+                self.emit(format!(".Lwritten_{id}:"));
+            }
+        }
+    }
+
+    fn compile(&mut self, program: &Program) {
+        for block in program {
+            self.compile_block(block);
+        }
+    }
+
+    fn compile_instruction(&mut self, op: &Op) {
+        match &op {
+            Op::Move(x) => self.emit(format!("add ${x}, {PTR}")),
+            Op::Modify(x) => self.emit(format!("addb ${}, ({PTR})", *x as u8)),
+            Op::Inp(x) => {
+                for _ in 0..(*x) {
+                    self.build_read();
+                }
+            }
+            Op::Outp(x) => {
+                for _ in 0..(*x) {
+                    self.build_write();
+                }
+            }
+            Op::DebugDump => self.build_dump_tape(),
+            Op::Call => self.build_call(),
+            Op::Fork => self.build_fork(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn compile_block(&mut self, block: &Block) {
+        use Block::*;
+        if !block.is_loop() {
+            let site_offset = self.next_site_offset();
+            self.count_site(site_offset);
+        }
+        match &block {
+            Simple(ref instrs) => instrs.iter().for_each(|i| {
+                self.set_location(i.pos);
+                self.compile_instruction(&i.op)
+            }),
+            Loop(ref program, span) => self.compile_loop(program, *span),
+            Procedure(ref program, span) => self.compile_procedure(program, *span),
+            Reset { span, .. } => {
+                self.set_location(span.start);
+                self.emit(format!("movb $0, ({PTR})"))
+            }
+            Multiply { ops, span } => {
+                self.set_location(span.start);
+                self.compile_multiply(ops)
+            }
+            _ => {}
+        }
+    }
+
+    fn compile_loop(&mut self, program: &Program, span: Span) {
+        let curr_loop_id = self.next_label_id();
+        let site_offset = self.next_site_offset();
+
+        self.set_location(span.start);
+        self.emit(format!("cmpb $0, ({PTR})"));
+        self.emit(format!("je .Lcont_{curr_loop_id}"));
+        self.emit(format!(".Lloop_{curr_loop_id}:"));
+        self.count_site(site_offset);
+
+        self.compile(program);
+
+        self.set_location(span.end);
+        self.emit(format!("cmpb $0, ({PTR})"));
+        self.emit(format!("jne .Lloop_{curr_loop_id}"));
+        self.emit(format!(".Lcont_{curr_loop_id}:"));
+    }
+
+    /// Emits the body as `bf_proc_N` into `procedures` and stores its address
+    /// in the slot of `bf_procs` numbered by the current cell
+    fn compile_procedure(&mut self, program: &Program, span: Span) {
+        self.uses_procedures = true;
+        self.procedure_id += 1;
+        let name = format!("bf_proc_{}", self.procedure_id);
+
+        let caller_code = std::mem::take(&mut self.code);
+        self.emit(".text");
+        self.emit(format!(".type {name}, @function"));
+        self.emit(format!("{name}:"));
+        // Realigns the stack for calls into libc
+        self.emit("sub $8, %rsp");
+        self.compile(program);
+        self.set_location(span.end);
+        self.emit("add $8, %rsp");
+        self.emit("ret");
+        self.emit(format!(".size {name}, .-{name}"));
+        let procedure_code = std::mem::replace(&mut self.code, caller_code);
+        self.procedures.push_str(&procedure_code);
+
+        self.set_location(span.start);
+        self.emit(format!("movzbl ({PTR}), %eax"));
+        self.emit("lea bf_procs(%rip), %rdx");
+        self.emit(format!("lea {name}(%rip), %rcx"));
+        self.emit("mov %rcx, (%rdx,%rax,8)");
+    }
+
+    /// Calls the procedure numbered by the current cell, if it is defined
+    fn build_call(&mut self) {
+        self.uses_procedures = true;
+        let id = self.next_label_id();
+        self.emit(format!("movzbl ({PTR}), %eax"));
+        self.emit("lea bf_procs(%rip), %rdx");
+        self.emit("mov (%rdx,%rax,8), %rax");
+        self.emit("test %rax, %rax");
+        self.emit(format!("jz .Lcall_{id}"));
+        self.emit("call *%rax");
+        self.emit(format!(".Lcall_{id}:"));
+    }
+
+    /// Brainfork's `Y`: the parent continues with the current cell set to 0,
+    /// the child one cell further right, with that cell set to 1
+    fn build_fork(&mut self) {
+        self.uses_fork = true;
+        let id = self.next_label_id();
+        // Unflushed output would otherwise be written by both processes
+        self.emit("xor %edi, %edi");
+        self.emit("call fflush@PLT");
+        self.emit("call fork@PLT");
+        self.emit("test %eax, %eax");
+        self.emit(format!("jnz .Lparent_{id}"));
+        self.emit(format!("inc {PTR}"));
+        self.emit(format!("movb $1, ({PTR})"));
+        self.emit(format!("jmp .Lforked_{id}"));
+        self.emit(format!(".Lparent_{id}:"));
+        self.emit(format!("movb $0, ({PTR})"));
+        self.emit(format!(".Lforked_{id}:"));
+    }
+
+    fn compile_multiply(&mut self, ops: &[(i32, i32)]) {
+        self.emit(format!("movzbl ({PTR}), %eax"));
+        for (diff, multiply_val) in ops {
+            self.emit(format!("imul ${multiply_val}, %eax, %ecx"));
+            self.emit(format!("add %cl, {diff}({PTR})"));
+        }
+        self.emit(format!("movb $0, ({PTR})"));
+    }
+}
+
+pub fn compile(program: Program, options: &CompileOptions) -> Result<()> {
+    let mut builder = AsmBuilder::new(options);
+    if options.profile {
+        builder.profile_sites = Some(profile::sites(&program));
+    }
+    if let Some(source) = &options.debug_source {
+        builder.create_debug_file(source);
+    }
+    match options.crate_type {
+        CrateType::Bin => builder.create_main_fn(),
+        CrateType::Cdylib => builder.create_run_tape_fn(),
+    }
+    builder.compile(&program);
+    builder.finalize();
+    if options.freestanding {
+        builder.code.push_str(RUNTIME_ASM);
+    }
+
+    if options.show_ir {
+        eprint!("{}", builder.code);
+    }
+
+    let asm_file = options.output.with_extension("s");
+    let object_file = options.object_file();
+
+    std::fs::write(&asm_file, builder.code)
+        .map_err(|e| BrainfcError::io(format!("Could not write {asm_file:?}"), e))?;
+
+    let status = std::process::Command::new("as")
+        .arg(&asm_file)
+        .arg("-o")
+        .arg(&object_file)
+        .status()
+        .map_err(|e| BrainfcError::Codegen(format!("Could not run assembler: {e}")))?;
+
+    if !status.success() {
+        return Err(BrainfcError::Codegen(format!(
+            "Assembling {asm_file:?} failed ({status})"
+        )));
+    }
+
+    std::fs::remove_file(&asm_file)
+        .map_err(|e| BrainfcError::io(format!("Could not remove {asm_file:?}"), e))
+}
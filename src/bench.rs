@@ -0,0 +1,338 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::time::Instant;
+
+use brainfc::optimizer::Optimizer;
+use brainfc::parser::Program;
+use brainfc::{profile, Backend, BrainfcError, CompileOptions, OptLevel};
+use clap::ValueEnum;
+
+use crate::app::{self, BenchArgs};
+
+/// Opt levels benchmarked if none are given
+const OPT_LEVELS: [OptLevel; 3] = [OptLevel::None, OptLevel::Default, OptLevel::Aggressive];
+
+#[derive(serde::Serialize)]
+struct Measurement {
+    program: String,
+    backend: String,
+    /// `None` for backends without opt levels
+    opt_level: Option<String>,
+    optimizer: bool,
+    runs: u32,
+    mean_ms: f64,
+    stddev_ms: f64,
+    /// Operations of the program executed, counted by a `--profile` build
+    instructions: u64,
+    binary_size: u64,
+}
+
+/// The name of `value` on the command line
+fn name(value: impl ValueEnum) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+struct Source {
+    path: std::path::PathBuf,
+    text: String,
+    input: Vec<u8>,
+}
+
+impl Source {
+    fn load(path: &Path, args: &BenchArgs) -> Result<Self, BrainfcError> {
+        let (text, _) = app::read_source(path, &args.extensions)?;
+        let input = match std::fs::read(path.with_extension("in")) {
+            Ok(input) => input,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(BrainfcError::io(format!("Could not open {path:?}"), e)),
+        };
+        Ok(Source {
+            path: path.to_path_buf(),
+            text,
+            input,
+        })
+    }
+
+    /// The program and options embedding its inline input, if it has any
+    fn program(
+        &self,
+        args: &BenchArgs,
+        optimizer: bool,
+    ) -> Result<(Program, CompileOptions), BrainfcError> {
+        let (program, inline_input) = app::parse_source(
+            &self.path,
+            &self.text,
+            args.dialect.as_deref(),
+            &args.extensions,
+        )?;
+        let mut options = CompileOptions::new();
+        if let Some(input) = inline_input {
+            options = options.embedded_input(input);
+        }
+        let program = match optimizer {
+            true => Optimizer::new().optimize(program),
+            false => program,
+        };
+        Ok((program, options))
+    }
+
+    fn run(&self, executable: &Path, stderr: Stdio) -> Result<Output, BrainfcError> {
+        let mut child = Command::new(executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(stderr)
+            .spawn()
+            .map_err(|e| BrainfcError::io(format!("Could not run {executable:?}"), e))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let input = self.input.clone();
+        std::thread::spawn(move || stdin.write_all(&input));
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| BrainfcError::io(format!("Could not wait for {executable:?}"), e))?;
+        if !output.status.success() {
+            return Err(BrainfcError::Runtime(format!(
+                "{} exited with {}",
+                self.path.display(),
+                output.status
+            )));
+        }
+        Ok(output)
+    }
+}
+
+/// Mean and sample standard deviation
+fn mean_stddev(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    if samples.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance.sqrt())
+}
+
+fn bench_source(
+    source: &Source,
+    args: &BenchArgs,
+    backends: &[Backend],
+    out_dir: &Path,
+) -> Result<Vec<Measurement>, BrainfcError> {
+    let stem = source.path.file_stem().unwrap().to_string_lossy();
+    let mut measurements = Vec::new();
+
+    for optimizer in [true, false] {
+        let (program, options) = source.program(args, optimizer)?;
+        let sites = profile::sites(&program);
+        let options = options
+            .backend(backends[0])
+            .profile(true)
+            .output(out_dir.join(format!("{stem}-profile")));
+        let executable = brainfc::compile_to_executable(program, &options)?;
+        let report = source.run(&executable, Stdio::piped())?;
+        let counts = profile::parse_report(&String::from_utf8_lossy(&report.stderr));
+        let instructions = profile::executed_ops(&sites, &counts);
+
+        for &backend in backends {
+            let opt_levels: Vec<Option<OptLevel>> = match backend.uses_opt_level() {
+                true if args.opt_levels.is_empty() => OPT_LEVELS.into_iter().map(Some).collect(),
+                true => args.opt_levels.iter().copied().map(Some).collect(),
+                false => vec![None],
+            };
+            for opt_level in opt_levels {
+                let (program, options) = source.program(args, optimizer)?;
+                let options = options
+                    .backend(backend)
+                    .opt_level(opt_level.unwrap_or_default())
+                    .output(out_dir.join(stem.as_ref()));
+                let executable = brainfc::compile_to_executable(program, &options)?;
+                let binary_size = std::fs::metadata(&executable)
+                    .map_err(|e| BrainfcError::io(format!("Could not open {executable:?}"), e))?
+                    .len();
+
+                for _ in 0..args.warmup {
+                    source.run(&executable, Stdio::null())?;
+                }
+                let mut samples = Vec::new();
+                for _ in 0..args.runs {
+                    let start = Instant::now();
+                    source.run(&executable, Stdio::null())?;
+                    samples.push(start.elapsed().as_secs_f64() * 1000.0);
+                }
+                let (mean_ms, stddev_ms) = mean_stddev(&samples);
+
+                measurements.push(Measurement {
+                    program: source.path.display().to_string(),
+                    backend: name(backend),
+                    opt_level: opt_level.map(name),
+                    optimizer,
+                    runs: args.runs,
+                    mean_ms,
+                    stddev_ms,
+                    instructions,
+                    binary_size,
+                });
+            }
+        }
+    }
+    Ok(measurements)
+}
+
+/// The measurements as a table with a header line
+fn table(measurements: &[Measurement]) -> String {
+    let width = measurements
+        .iter()
+        .map(|m| m.program.len())
+        .chain(["program".len()])
+        .max()
+        .unwrap();
+    let mut table = String::new();
+    writeln!(
+        table,
+        "{:<width$}  {:<7}  {:<10}  {:<9}  {:>10}  {:>10}  {:>14}  {:>10}",
+        "program",
+        "backend",
+        "opt-level",
+        "optimizer",
+        "mean ms",
+        "stddev ms",
+        "instructions",
+        "size"
+    )
+    .unwrap();
+    for m in measurements {
+        writeln!(
+            table,
+            "{:<width$}  {:<7}  {:<10}  {:<9}  {:>10.2}  {:>10.2}  {:>14}  {:>10}",
+            m.program,
+            m.backend,
+            m.opt_level.as_deref().unwrap_or("-"),
+            if m.optimizer { "on" } else { "off" },
+            m.mean_ms,
+            m.stddev_ms,
+            m.instructions,
+            m.binary_size
+        )
+        .unwrap();
+    }
+    table
+}
+
+/// The measurements as a JSON array with one object per configuration
+fn json(measurements: &[Measurement]) -> Result<String, BrainfcError> {
+    serde_json::to_string_pretty(measurements).map_err(|e| BrainfcError::Runtime(e.to_string()))
+}
+
+pub fn run(args: BenchArgs) -> Result<(), BrainfcError> {
+    let backends = match args.backends.is_empty() {
+        true => vec![
+            #[cfg(feature = "llvm")]
+            Backend::Llvm,
+            Backend::Asm,
+        ],
+        false => args.backends.clone(),
+    };
+    if backends.contains(&Backend::Wasm) {
+        return Err(BrainfcError::Target(
+            "the wasm backend does not produce executables to benchmark".to_string(),
+        ));
+    }
+
+    let sources = args
+        .paths
+        .iter()
+        .map(|path| Source::load(path, &args))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let out_dir = std::env::temp_dir().join(format!("brainfc-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir)
+        .map_err(|e| BrainfcError::io(format!("Could not create {out_dir:?}"), e))?;
+    let measurements = sources
+        .iter()
+        .map(|source| bench_source(source, &args, &backends, &out_dir))
+        .collect::<Result<Vec<_>, _>>();
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let measurements: Vec<Measurement> = measurements?.into_iter().flatten().collect();
+
+    if args.json {
+        println!("{}", json(&measurements)?);
+    } else {
+        print!("{}", table(&measurements));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn measurement(program: &str, opt_level: Option<&str>) -> Measurement {
+        Measurement {
+            program: program.to_string(),
+            backend: "asm".to_string(),
+            opt_level: opt_level.map(str::to_string),
+            optimizer: true,
+            runs: 4,
+            mean_ms: 2.5,
+            stddev_ms: 0.25,
+            instructions: 123456,
+            binary_size: 16384,
+        }
+    }
+
+    #[test]
+    fn sample_statistics() {
+        let (mean, stddev) = mean_stddev(&[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(mean, 2.5);
+        assert!((stddev - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!(mean_stddev(&[7.0]), (7.0, 0.0));
+    }
+
+    #[test]
+    fn json_has_one_object_per_measurement() {
+        let measurements = [
+            measurement("a.b", None),
+            measurement("b.b", Some("default")),
+        ];
+        let json: serde_json::Value = serde_json::from_str(&json(&measurements).unwrap()).unwrap();
+        let objects = json.as_array().unwrap();
+        assert_eq!(objects.len(), 2);
+        let keys: Vec<&str> = objects[0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "backend",
+                "binary_size",
+                "instructions",
+                "mean_ms",
+                "opt_level",
+                "optimizer",
+                "program",
+                "runs",
+                "stddev_ms"
+            ]
+        );
+        assert_eq!(objects[0]["opt_level"], serde_json::Value::Null);
+        assert_eq!(objects[1]["opt_level"], "default");
+        assert_eq!(objects[1]["instructions"], 123456);
+    }
+
+    #[test]
+    fn table_aligns_the_instruction_counts() {
+        let table = table(&[measurement("examples/long_name.b", None)]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        // Right-aligned under their header
+        let header_end = lines[0].find("instructions").unwrap() + "instructions".len();
+        let count_end = lines[1].find("123456").unwrap() + "123456".len();
+        assert_eq!(header_end, count_end);
+        assert!(lines[1].starts_with("examples/long_name.b  asm      -           on"));
+    }
+}
@@ -0,0 +1,84 @@
+//! Interface of programs compiled with `--crate-type cdylib`.
+
+use std::path::Path;
+
+use crate::options::IoMode;
+
+/// Number of cells on the tape `bf_run` allocates
+pub const TAPE_SIZE: usize = 30_000;
+
+/// C header declaring the entry points for the given I/O mode
+pub fn header(output: &Path, io: IoMode) -> String {
+    let name = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let guard: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let (run_doc, run_params, run_tape_params) = match io {
+        IoMode::Stdio => (
+            "`,` reads from stdin, storing 255 on EOF, and `.` writes to stdout.\n * Returns 0.",
+            "void",
+            "uint8_t *tape",
+        ),
+        IoMode::Buffer => (
+            "`,` reads from `in` and stores 255 once\n * all `in_len` bytes are consumed. `.` writes to `out`; the number of bytes\n * written is stored in `*out_len`.\n * Returns 0, or 1 if the output did not fit in `out_cap` bytes.",
+            "const uint8_t *in, size_t in_len, uint8_t *out, size_t out_cap, size_t *out_len",
+            "uint8_t *tape, const uint8_t *in, size_t in_len, uint8_t *out, size_t out_cap,\n                size_t *out_len",
+        ),
+        IoMode::Callback => (
+            "`,` stores the result of `read(ctx)`, which\n * returns -1 (stored as 255) on EOF. `.` calls `write(ctx, cell)`.\n * Returns 0.",
+            "bf_read_fn read, bf_write_fn write, void *ctx",
+            "uint8_t *tape, bf_read_fn read, bf_write_fn write, void *ctx",
+        ),
+    };
+    let callback_types = match io {
+        IoMode::Callback => {
+            "\ntypedef int (*bf_read_fn)(void *ctx);\ntypedef int (*bf_write_fn)(void *ctx, int c);\n"
+        }
+        _ => "",
+    };
+
+    format!(
+        r#"/* Generated by brainfc */
+#ifndef BRAINFC_{guard}_H
+#define BRAINFC_{guard}_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {{
+#endif
+
+/* Minimum number of cells of a tape passed to bf_run_tape */
+#define BF_TAPE_SIZE {TAPE_SIZE}
+{callback_types}
+/*
+ * Runs the program on a zeroed tape. {run_doc}
+ */
+int bf_run({run_params});
+
+/*
+ * Like bf_run, but on a caller-provided tape of at least BF_TAPE_SIZE cells,
+ * which is left in the state the program finishes in.
+ */
+int bf_run_tape({run_tape_params});
+
+#ifdef __cplusplus
+}}
+#endif
+
+#endif
+"#
+    )
+}
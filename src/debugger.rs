@@ -0,0 +1,525 @@
+use std::io::{BufRead, Write};
+
+use brainfc::interpreter::{Instr, Interpreter};
+use brainfc::lexer::Pos;
+use brainfc::{BrainfcError, Extension};
+use colored::Colorize;
+
+use crate::app::{self, DebugArgs};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute the next instruction (n times)
+  n, next              like step, but runs a loop at the current position to completion
+  c, continue          run until a breakpoint, a watchpoint or the end of the program
+  b, break LINE[:COL]  stop before the first instruction at or after LINE:COL
+  w, watch CELL        stop whenever the value of CELL changes
+  d, delete [N]        delete breakpoint N, or all breakpoints and watchpoints
+  i, info              list breakpoints and watchpoints
+  t, tape [RADIUS]     show the cells around the tape pointer
+  l, list              show the current source line
+  r, restart           start the program over, keeping breakpoints
+  q, quit              leave the debugger
+An empty line repeats the previous command. Every `#` command sets a breakpoint.";
+
+struct Breakpoint {
+    pos: Pos,
+    pc: usize,
+}
+
+/// Brainfork threads have a tape each, so a watchpoint is on a cell of the
+/// thread that was current when it was set
+struct Watchpoint {
+    thread: usize,
+    cell: usize,
+    /// `None` until the thread is forked
+    value: Option<u8>,
+}
+
+enum Stop {
+    Finished,
+    Breakpoint(usize),
+    Watchpoint {
+        thread: usize,
+        cell: usize,
+        old: u8,
+        new: u8,
+    },
+    Done,
+}
+
+struct Debugger {
+    source: String,
+    program: brainfc::parser::Program,
+    input: Vec<u8>,
+    interpreter: Interpreter,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+fn parse_pos(arg: &str) -> Option<Pos> {
+    let (line, col) = match arg.split_once(':') {
+        Some((line, col)) => (line, col.parse().ok()?),
+        None => (arg, 1),
+    };
+    Some(Pos {
+        line: line.parse().ok()?,
+        col,
+    })
+}
+
+/// Splits `line` into a command and its argument. An empty line repeats
+/// `last`, which is updated to the line that runs.
+fn next_command(line: &str, last: &mut String) -> Option<(String, Option<String>)> {
+    let line = match line.trim() {
+        "" => last.clone(),
+        line => line.to_string(),
+    };
+    *last = line.clone();
+
+    let mut words = line.split_whitespace();
+    let command = words.next()?.to_string();
+    Some((command, words.next().map(str::to_string)))
+}
+
+impl Debugger {
+    fn new(source: String, program: brainfc::parser::Program, input: Vec<u8>) -> Self {
+        let interpreter = Interpreter::new(&program).with_input(input.clone());
+
+        let mut debugger = Debugger {
+            source,
+            program,
+            input,
+            interpreter,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        };
+
+        let marks: Vec<usize> = (0..debugger.interpreter.code().len())
+            .filter(|&pc| matches!(debugger.interpreter.code()[pc], Instr::DebugDump))
+            .collect();
+        for pc in marks {
+            let pos = debugger.interpreter.positions()[pc];
+            debugger.push_breakpoint(pos, pc);
+        }
+
+        debugger
+    }
+
+    /// First instruction at or after `pos`
+    fn resolve(&self, pos: Pos) -> Option<usize> {
+        self.interpreter.positions().iter().position(|p| *p >= pos)
+    }
+
+    fn add_breakpoint(&mut self, pos: Pos) {
+        match self.resolve(pos) {
+            Some(pc) => self.push_breakpoint(pos, pc),
+            None => println!("no instruction at or after {pos}"),
+        }
+    }
+
+    fn push_breakpoint(&mut self, pos: Pos, pc: usize) {
+        let at = self.interpreter.positions()[pc];
+        println!("breakpoint {} at {at}", self.breakpoints.len() + 1);
+        self.breakpoints.push(Breakpoint { pos, pc });
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        for watchpoint in self.watchpoints.iter_mut() {
+            let Some(tape) = self.interpreter.thread_tape(watchpoint.thread) else {
+                continue;
+            };
+            let new = tape[watchpoint.cell];
+            match watchpoint.value.replace(new) {
+                Some(old) if old != new => {
+                    return Some(Stop::Watchpoint {
+                        thread: watchpoint.thread,
+                        cell: watchpoint.cell,
+                        old,
+                        new,
+                    });
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Steps at least once, then until `done` holds or something stops the
+    /// program
+    fn resume(&mut self, done: impl Fn(&Interpreter) -> bool) -> Result<Stop, BrainfcError> {
+        loop {
+            if !self.interpreter.step()? {
+                return Ok(Stop::Finished);
+            }
+            if let Some(stop) = self.check_watchpoints() {
+                return Ok(stop);
+            }
+            if self.interpreter.is_finished() {
+                return Ok(Stop::Finished);
+            }
+            let pc = self.interpreter.pc();
+            if let Some(n) = self.breakpoints.iter().position(|b| b.pc == pc) {
+                return Ok(Stop::Breakpoint(n + 1));
+            }
+            if done(&self.interpreter) {
+                return Ok(Stop::Done);
+            }
+        }
+    }
+
+    fn flush_output(&mut self) {
+        let output = self.interpreter.take_output();
+        if !output.is_empty() {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&output).unwrap();
+            if !output.ends_with(b"\n") {
+                stdout.write_all(b"\n").unwrap();
+            }
+            stdout.flush().unwrap();
+        }
+    }
+
+    fn show_location(&self) {
+        let Some(pos) = self.interpreter.pos() else {
+            println!("program finished after {} steps", self.interpreter.steps());
+            return;
+        };
+        if self.interpreter.threads() > 1 {
+            println!(
+                "thread {} of {}",
+                self.interpreter.current_thread() + 1,
+                self.interpreter.threads()
+            );
+        }
+        // Code generated for `.bfl` programs may have no position
+        let (Some(line_index), Some(col_index)) = (pos.line.checked_sub(1), pos.col.checked_sub(1))
+        else {
+            println!("{} no source position", "      |".dimmed());
+            return;
+        };
+        let line = self
+            .source
+            .lines()
+            .nth(line_index as usize)
+            .unwrap_or_default();
+        println!("{} {line}", format!("{:>5} |", pos.line).dimmed());
+        println!(
+            "{} {}{}",
+            "      |".dimmed(),
+            " ".repeat(col_index as usize),
+            "^".yellow().bold()
+        );
+    }
+
+    fn show_tape(&self, radius: usize) {
+        let pointer = self.interpreter.pointer();
+        let start = pointer.saturating_sub(radius);
+        let end = (pointer + radius + 1).min(self.interpreter.tape().len());
+        let cells: Vec<String> = (start..end)
+            .map(|i| {
+                let cell = format!("{i}:{}", self.interpreter.tape()[i]);
+                if i == pointer {
+                    format!("[{cell}]").green().bold().to_string()
+                } else {
+                    cell
+                }
+            })
+            .collect();
+        println!("{}", cells.join(" "));
+    }
+
+    fn report(&mut self, stop: Stop) {
+        self.flush_output();
+        match stop {
+            Stop::Finished => {}
+            Stop::Breakpoint(n) => println!("breakpoint {n}"),
+            Stop::Watchpoint {
+                thread,
+                cell,
+                old,
+                new,
+            } => {
+                if self.interpreter.threads() > 1 {
+                    print!("thread {}: ", thread + 1);
+                }
+                println!("cell {cell} changed: {old} -> {new}");
+            }
+            Stop::Done => {}
+        }
+        self.show_location();
+    }
+
+    /// Runs one command, returns `false` to quit
+    fn execute(&mut self, command: &str, arg: Option<&str>) -> Result<bool, BrainfcError> {
+        match command {
+            "s" | "step" => {
+                let count: u64 = arg.and_then(|n| n.parse().ok()).unwrap_or(1);
+                let target = self.interpreter.steps().saturating_add(count);
+                let stop = self.resume(|i| i.steps() >= target)?;
+                self.report(stop);
+            }
+            "n" | "next" => {
+                let stop = match self.interpreter.code().get(self.interpreter.pc()) {
+                    Some(Instr::LoopStart { end }) => {
+                        let after = end + 1;
+                        self.resume(|i| i.pc() == after)?
+                    }
+                    _ => self.resume(|_| true)?,
+                };
+                self.report(stop);
+            }
+            "c" | "continue" => {
+                let stop = self.resume(|_| false)?;
+                self.report(stop);
+            }
+            "b" | "break" => match arg.and_then(parse_pos) {
+                Some(pos) => self.add_breakpoint(pos),
+                None => println!("usage: break LINE[:COL]"),
+            },
+            "w" | "watch" => match arg.and_then(|cell| cell.parse::<usize>().ok()) {
+                Some(cell) if cell < self.interpreter.tape().len() => {
+                    let value = self.interpreter.tape()[cell];
+                    self.watchpoints.push(Watchpoint {
+                        thread: self.interpreter.current_thread(),
+                        cell,
+                        value: Some(value),
+                    });
+                    println!("watching cell {cell} (currently {value})");
+                }
+                _ => println!("usage: watch CELL"),
+            },
+            "d" | "delete" => match arg.map(|n| n.parse::<usize>()) {
+                None => {
+                    self.breakpoints.clear();
+                    self.watchpoints.clear();
+                }
+                Some(Ok(n)) if n >= 1 && n <= self.breakpoints.len() => {
+                    self.breakpoints.remove(n - 1);
+                }
+                Some(_) => println!("no such breakpoint"),
+            },
+            "i" | "info" => {
+                for (n, breakpoint) in self.breakpoints.iter().enumerate() {
+                    let at = self.interpreter.positions()[breakpoint.pc];
+                    println!("breakpoint {} at {at} (set at {})", n + 1, breakpoint.pos);
+                }
+                for watchpoint in &self.watchpoints {
+                    println!(
+                        "watchpoint on cell {} of thread {}",
+                        watchpoint.cell,
+                        watchpoint.thread + 1
+                    );
+                }
+            }
+            "t" | "tape" => {
+                let radius = arg.and_then(|r| r.parse().ok()).unwrap_or(8);
+                self.show_tape(radius);
+            }
+            "l" | "list" => self.show_location(),
+            "r" | "restart" => {
+                self.interpreter = Interpreter::new(&self.program).with_input(self.input.clone());
+                for watchpoint in self.watchpoints.iter_mut() {
+                    watchpoint.value = (watchpoint.thread == 0).then_some(0);
+                }
+                self.show_location();
+            }
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => println!("{HELP}"),
+            _ => println!("unknown command `{command}`, try `help`"),
+        }
+        Ok(true)
+    }
+}
+
+pub fn run(args: DebugArgs) -> Result<(), BrainfcError> {
+    // `#` always sets a breakpoint here
+    let mut extensions = args.extensions.clone();
+    if !extensions.contains(&Extension::DebugDump) {
+        extensions.push(Extension::DebugDump);
+    }
+    let (source, source_map) = app::read_source(&args.path, &extensions)?;
+    let map_error = |e| match &source_map {
+        Some(map) => map.map_error(e),
+        None => e,
+    };
+    let (program, inline_input) =
+        app::parse_source(&args.path, &source, args.dialect.as_deref(), &extensions)
+            .map_err(map_error)?;
+    let input = match &args.input {
+        Some(path) => std::fs::read(path)
+            .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?,
+        None => inline_input.unwrap_or_default().as_bytes().to_vec(),
+    };
+
+    let mut debugger = Debugger::new(source.clone(), program, input);
+    println!(
+        "{} instructions, type `help` for commands",
+        debugger.interpreter.code().len()
+    );
+    debugger.show_location();
+
+    let stdin = std::io::stdin();
+    let mut last_command = String::new();
+    loop {
+        print!("{} ", "(bfdb)".bold());
+        std::io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| BrainfcError::io("Could not read command", e))?
+            == 0
+        {
+            return Ok(());
+        }
+        let Some((command, arg)) = next_command(&line, &mut last_command) else {
+            continue;
+        };
+        match debugger.execute(&command, arg.as_deref()) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(err) => {
+                let err = map_error(err);
+                debugger.flush_output();
+                println!("{} {err}", "error: ".red().bold());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn debugger_for(source: &str, dialect: &brainfc::Dialect) -> Debugger {
+        let tokens = brainfc::lex_with(source, dialect, &[Extension::DebugDump]);
+        let program = brainfc::parse(tokens).unwrap();
+        Debugger::new(source.to_string(), program, Vec::new())
+    }
+
+    fn debugger(source: &str) -> Debugger {
+        debugger_for(source, &brainfc::Dialect::Brainfuck)
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(parse_pos("3:7"), Some(Pos { line: 3, col: 7 }));
+        assert_eq!(parse_pos("4"), Some(Pos { line: 4, col: 1 }));
+        assert_eq!(parse_pos("3:"), None);
+        assert_eq!(parse_pos("x:1"), None);
+    }
+
+    #[test]
+    fn empty_lines_repeat_the_last_command() {
+        let mut last = String::new();
+        assert_eq!(next_command("  \n", &mut last), None);
+        assert_eq!(
+            next_command("step 5\n", &mut last),
+            Some(("step".to_string(), Some("5".to_string())))
+        );
+        assert_eq!(
+            next_command("\n", &mut last),
+            Some(("step".to_string(), Some("5".to_string())))
+        );
+        assert_eq!(
+            next_command("tape", &mut last),
+            Some(("tape".to_string(), None))
+        );
+        assert_eq!(last, "tape");
+    }
+
+    #[test]
+    fn huge_step_counts_run_to_the_end() {
+        let mut debugger = debugger("+++[-]");
+        let step = Some("18446744073709551615");
+        assert!(debugger.execute("step", step).unwrap());
+        assert!(debugger.interpreter.is_finished());
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut debugger = debugger("+\n>+#+\n.");
+        debugger.add_breakpoint(Pos { line: 2, col: 1 });
+        assert!(matches!(
+            debugger.resume(|_| false),
+            Ok(Stop::Breakpoint(2))
+        ));
+        assert_eq!(debugger.interpreter.pos(), Some(Pos { line: 2, col: 1 }));
+        // The `#` command set breakpoint 1
+        assert!(matches!(
+            debugger.resume(|_| false),
+            Ok(Stop::Breakpoint(1))
+        ));
+        assert_eq!(debugger.interpreter.pos(), Some(Pos { line: 2, col: 3 }));
+        assert!(matches!(debugger.resume(|_| false), Ok(Stop::Finished)));
+    }
+
+    #[test]
+    fn stops_when_a_watched_cell_changes() {
+        let mut debugger = debugger("+>+++>+");
+        debugger.execute("watch", Some("1")).unwrap();
+        let stop = debugger.resume(|_| false);
+        assert!(matches!(
+            stop,
+            Ok(Stop::Watchpoint {
+                thread: 0,
+                cell: 1,
+                old: 0,
+                new: 3
+            })
+        ));
+        assert!(matches!(debugger.resume(|_| false), Ok(Stop::Finished)));
+    }
+
+    #[test]
+    fn watchpoints_stay_on_their_thread() {
+        // The child forked by `Y` starts on cell 1 of its own tape
+        let mut debugger = debugger_for("+++Y>++++", &brainfc::Dialect::Brainfork);
+        debugger.execute("watch", Some("1")).unwrap();
+        let stop = debugger.resume(|_| false);
+        assert!(matches!(
+            stop,
+            Ok(Stop::Watchpoint {
+                thread: 0,
+                cell: 1,
+                old: 0,
+                new: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn steps_through_bfl_programs() {
+        let source = "var n = 2; // #\nwhile n != 0 {\n    print '0' + n;\n    n -= 1;\n}\n";
+        let program = brainfc::lang::compile(source).unwrap();
+        let mut debugger = Debugger::new(source.to_string(), program, Vec::new());
+        // `#` in a comment is no command
+        assert!(debugger.breakpoints.is_empty());
+        while !debugger.interpreter.is_finished() {
+            debugger.execute("step", None).unwrap();
+        }
+        debugger.show_location();
+    }
+
+    #[test]
+    fn shows_instructions_without_a_position() {
+        let token = brainfc::lexer::Token {
+            op: brainfc::lexer::Op::Modify(1),
+            pos: Pos::default(),
+        };
+        let program = vec![brainfc::parser::Block::Simple(vec![token])];
+        let debugger = Debugger::new("var n = 1;".to_string(), program, Vec::new());
+        debugger.show_location();
+    }
+
+    #[test]
+    fn next_runs_a_loop_to_completion() {
+        let mut debugger = debugger("++[>+<-]>.");
+        assert!(matches!(debugger.resume(|_| true), Ok(Stop::Done)));
+        debugger.execute("next", None).unwrap();
+        assert_eq!(debugger.interpreter.tape()[..2], [0, 2]);
+        assert_eq!(debugger.interpreter.pos(), Some(Pos { line: 1, col: 9 }));
+    }
+}
@@ -0,0 +1,182 @@
+//! Languages `lexer::parse_with` reads. Besides brainfuck and pbrain these are
+//! token substitutions, which spell each of the eight commands with a
+//! multi-character token and therefore share the parser, optimizer and
+//! backends with brainfuck.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{BrainfcError, Result};
+
+const COMMANDS: [char; 8] = ['+', '-', '>', '<', '.', ',', '[', ']'];
+
+const OOK: [(char, &str); 8] = [
+    ('>', "Ook. Ook?"),
+    ('<', "Ook? Ook."),
+    ('+', "Ook. Ook."),
+    ('-', "Ook! Ook!"),
+    ('.', "Ook! Ook."),
+    (',', "Ook. Ook!"),
+    ('[', "Ook! Ook?"),
+    (']', "Ook? Ook!"),
+];
+
+const BLUB: [(char, &str); 8] = [
+    ('>', "Blub. Blub?"),
+    ('<', "Blub? Blub."),
+    ('+', "Blub. Blub."),
+    ('-', "Blub! Blub!"),
+    ('.', "Blub! Blub."),
+    (',', "Blub. Blub!"),
+    ('[', "Blub! Blub?"),
+    (']', "Blub? Blub!"),
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Brainfuck,
+    /// Adds `(`, `)` and `:` to define and call procedures numbered by the
+    /// current cell
+    Pbrain,
+    /// Adds `Y`, which forks the program. The parent continues with the
+    /// current cell set to 0, the child on a copy of the tape with the
+    /// pointer moved one cell right and that cell set to 1.
+    Brainfork,
+    Ook,
+    Blub,
+    /// Tokens read from a mapping file, see `Dialect::load`
+    Custom(TokenTable),
+}
+
+/// The token of every brainfuck command. A space in a token matches any
+/// run of whitespace in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenTable {
+    tokens: Vec<(String, char)>,
+}
+
+#[derive(serde::Deserialize)]
+struct Mapping {
+    tokens: BTreeMap<String, String>,
+}
+
+impl TokenTable {
+    fn new(tokens: impl IntoIterator<Item = (char, impl Into<String>)>) -> Self {
+        let mut tokens: Vec<(String, char)> = tokens
+            .into_iter()
+            .map(|(command, token)| (token.into(), command))
+            .collect();
+        // Longest first, so a token wins over its prefixes
+        tokens.sort_by_key(|(token, _)| std::cmp::Reverse(token.len()));
+        TokenTable { tokens }
+    }
+
+    /// The tokens with the brainfuck command each stands for
+    pub fn tokens(&self) -> impl Iterator<Item = (&str, char)> {
+        self.tokens
+            .iter()
+            .map(|(token, command)| (token.as_str(), *command))
+    }
+
+    fn from_mapping(mapping: Mapping) -> std::result::Result<Self, String> {
+        let mut tokens = Vec::new();
+        for (command, token) in mapping.tokens {
+            let command = match command.chars().collect::<Vec<_>>()[..] {
+                [c] if COMMANDS.contains(&c) => c,
+                _ => return Err(format!("`{command}` is not a brainfuck command")),
+            };
+            if token.trim().is_empty() {
+                return Err(format!("the token of `{command}` is empty"));
+            }
+            let token = token.split_whitespace().collect::<Vec<_>>().join(" ");
+            if let Some((other, _)) = tokens.iter().find(|(_, t)| *t == token) {
+                return Err(format!(
+                    "`{other}` and `{command}` share the token `{token}`"
+                ));
+            }
+            tokens.push((command, token));
+        }
+        if let Some(missing) = COMMANDS
+            .iter()
+            .find(|c| !tokens.iter().any(|(command, _)| command == *c))
+        {
+            return Err(format!("no token for `{missing}`"));
+        }
+        Ok(TokenTable::new(tokens))
+    }
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "brainfuck" => Some(Dialect::Brainfuck),
+            "pbrain" => Some(Dialect::Pbrain),
+            "brainfork" => Some(Dialect::Brainfork),
+            "ook" => Some(Dialect::Ook),
+            "blub" => Some(Dialect::Blub),
+            _ => None,
+        }
+    }
+
+    /// The dialect of a source file named `*.ook`, `*.blub` or `*.pbrain`
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ook" => Some(Dialect::Ook),
+            "blub" => Some(Dialect::Blub),
+            "pbrain" => Some(Dialect::Pbrain),
+            _ => None,
+        }
+    }
+
+    /// Reads a token mapping from a `.toml` or `.json` file with a `tokens`
+    /// table mapping each of the eight commands to its token:
+    ///
+    /// ```toml
+    /// [tokens]
+    /// ">" = "moO"
+    /// "<" = "mOo"
+    /// # ...
+    /// ```
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?;
+        let mapping = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str::<Mapping>(&text).map_err(|e| e.to_string()),
+            _ => toml::from_str::<Mapping>(&text).map_err(|e| e.to_string()),
+        };
+        mapping
+            .and_then(TokenTable::from_mapping)
+            .map(Dialect::Custom)
+            .map_err(|e| BrainfcError::Parse(format!("Invalid dialect {path:?}: {e}")))
+    }
+
+    /// `--dialect`: a built-in name or a mapping file. Without one the
+    /// extension of `source` decides, falling back to brainfuck.
+    pub fn resolve(name: Option<&str>, source: &Path) -> Result<Self> {
+        let Some(name) = name else {
+            return Ok(Self::from_extension(source).unwrap_or_default());
+        };
+        if let Some(dialect) = Self::from_name(name) {
+            return Ok(dialect);
+        }
+        let path = Path::new(name);
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml" | "json") => Self::load(path),
+            _ => Err(BrainfcError::Parse(format!(
+                "Unknown dialect `{name}`, expected brainfuck, pbrain, brainfork, ook, blub or a .toml/.json mapping"
+            ))),
+        }
+    }
+
+    /// The tokens of a token substitution dialect, `None` for dialects of
+    /// single-character commands
+    pub fn token_table(&self) -> Option<TokenTable> {
+        match self {
+            Dialect::Brainfuck | Dialect::Pbrain | Dialect::Brainfork => None,
+            Dialect::Ook => Some(TokenTable::new(OOK)),
+            Dialect::Blub => Some(TokenTable::new(BLUB)),
+            Dialect::Custom(table) => Some(table.clone()),
+        }
+    }
+}
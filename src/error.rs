@@ -0,0 +1,120 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::lexer::Pos;
+
+/// A position in the source, in `file` if it is not the program itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    pub pos: Pos,
+}
+
+impl From<Pos> for Location {
+    fn from(pos: Pos) -> Self {
+        Location { file: None, pos }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file.display(), self.pos),
+            None => write!(f, "{}", self.pos),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BrainfcError {
+    Io {
+        context: String,
+        source: std::io::Error,
+    },
+    Parse(String),
+    Codegen(String),
+    Target(String),
+    Link(String),
+    /// The interpreter hit an error while running a program
+    Runtime(String),
+    /// `error` about the source at `locations`
+    At {
+        error: Box<BrainfcError>,
+        locations: Vec<Location>,
+    },
+}
+
+impl BrainfcError {
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        BrainfcError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Attaches a location in the source the error is about, after those
+    /// attached before
+    pub fn at(self, location: impl Into<Location>) -> Self {
+        match self {
+            BrainfcError::At {
+                error,
+                mut locations,
+            } => {
+                locations.push(location.into());
+                BrainfcError::At { error, locations }
+            }
+            error => BrainfcError::At {
+                error: Box::new(error),
+                locations: vec![location.into()],
+            },
+        }
+    }
+
+    /// Process exit status for this kind of failure. 1 is left for panics
+    /// and 2 for command line usage errors reported by clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BrainfcError::Io { .. } => 3,
+            BrainfcError::Parse(_) => 4,
+            BrainfcError::Codegen(_) => 5,
+            BrainfcError::Target(_) => 6,
+            BrainfcError::Link(_) => 7,
+            BrainfcError::Runtime(_) => 8,
+            BrainfcError::At { error, .. } => error.exit_code(),
+        }
+    }
+}
+
+impl fmt::Display for BrainfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrainfcError::Io { context, source } => write!(f, "{context}: {source}"),
+            BrainfcError::Parse(msg) => write!(f, "parse error: {msg}"),
+            BrainfcError::Codegen(msg) => write!(f, "code generation failed: {msg}"),
+            BrainfcError::Target(msg) => write!(f, "target error: {msg}"),
+            BrainfcError::Link(msg) => write!(f, "linking failed: {msg}"),
+            BrainfcError::Runtime(msg) => write!(f, "runtime error: {msg}"),
+            BrainfcError::At { error, locations } => {
+                write!(f, "{error} at ")?;
+                for (i, location) in locations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(f, "{location}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BrainfcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BrainfcError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, BrainfcError>;
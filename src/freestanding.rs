@@ -0,0 +1,44 @@
+//! Minimal x86-64 Linux runtime for executables that are not linked against
+//! libc. It provides `_start` and the two libc functions the generated code
+//! calls, implemented directly with `read`, `write` and `exit` syscalls.
+
+pub const RUNTIME_ASM: &str = r#"
+    .text
+    .globl _start
+_start:
+    xor %ebp, %ebp
+    and $-16, %rsp
+    call main
+    mov %eax, %edi
+    mov $60, %eax
+    syscall
+
+getchar:
+    sub $24, %rsp
+    xor %eax, %eax
+    xor %edi, %edi
+    lea 8(%rsp), %rsi
+    mov $1, %edx
+    syscall
+    cmp $1, %rax
+    jne 1f
+    movzbl 8(%rsp), %eax
+    add $24, %rsp
+    ret
+1:
+    mov $-1, %eax
+    add $24, %rsp
+    ret
+
+putchar:
+    sub $24, %rsp
+    mov %dil, 8(%rsp)
+    mov $1, %eax
+    mov $1, %edi
+    lea 8(%rsp), %rsi
+    mov $1, %edx
+    syscall
+    movzbl 8(%rsp), %eax
+    add $24, %rsp
+    ret
+"#;
@@ -0,0 +1,128 @@
+//! Generates brainfuck that prints a fixed text. Candidates start with a
+//! multiplication loop like `++++++++[>+++++++++>++++<<-]`, which `MultiplyOpt`
+//! later turns back into a multiplication, setting up cells close to the
+//! bytes to print; each byte is then printed from the cell that is cheapest
+//! to reach and adjust, which keeps the value for the next bytes.
+
+use crate::error::{BrainfcError, Result};
+
+/// Largest counter of the setup loop tried
+const MAX_FACTOR: u8 = 20;
+
+/// `+` or `-` repeated to add `diff` to a cell, the shorter way around
+fn adjust(diff: i32) -> String {
+    let diff = diff.rem_euclid(256);
+    if diff <= 128 {
+        "+".repeat(diff as usize)
+    } else {
+        "-".repeat(256 - diff as usize)
+    }
+}
+
+fn adjust_cost(from: u8, to: u8) -> usize {
+    let diff = (to as i32 - from as i32).rem_euclid(256) as usize;
+    diff.min(256 - diff)
+}
+
+fn moves(from: usize, to: usize) -> String {
+    if to >= from {
+        ">".repeat(to - from)
+    } else {
+        "<".repeat(from - to)
+    }
+}
+
+/// Prints `bytes` with the tape set up as `cells` and the pointer on `pointer`
+fn print_from(mut cells: Vec<u8>, mut pointer: usize, bytes: &[u8]) -> String {
+    let mut program = String::new();
+    for &byte in bytes {
+        let (cell, _) = cells
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (i, i.abs_diff(pointer) + adjust_cost(value, byte)))
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap();
+        program += &moves(pointer, cell);
+        program += &adjust(byte as i32 - cells[cell] as i32);
+        program.push('.');
+        cells[cell] = byte;
+        pointer = cell;
+    }
+    program
+}
+
+/// A setup loop running `factor` times, adding a multiple of `factor` close
+/// to the bytes to the cells right of the counter, then the bytes
+fn with_setup(bytes: &[u8], factor: u8) -> String {
+    let mut multiples: Vec<u8> = bytes
+        .iter()
+        .map(|&byte| ((byte as u32 + factor as u32 / 2) / factor as u32) as u8)
+        .filter(|&multiple| multiple != 0)
+        .collect();
+    multiples.sort_unstable();
+    multiples.dedup();
+
+    let mut program = "+".repeat(factor as usize) + "[";
+    for multiple in &multiples {
+        program.push('>');
+        program += &"+".repeat(*multiple as usize);
+    }
+    program += &"<".repeat(multiples.len());
+    program += "-]";
+
+    let cells = std::iter::once(0)
+        .chain(multiples.iter().map(|m| m.wrapping_mul(factor)))
+        .collect();
+    program + &print_from(cells, 0, bytes)
+}
+
+/// The shortest program found that prints `bytes`, checked by running it on
+/// the interpreter
+pub fn print_program(bytes: &[u8]) -> Result<String> {
+    let program = (2..=MAX_FACTOR)
+        .map(|factor| with_setup(bytes, factor))
+        .chain([print_from(vec![0], 0, bytes)])
+        .min_by_key(|program| program.len())
+        .unwrap();
+
+    let parsed = crate::parse(crate::lex(&program))?;
+    let output = crate::interpreter::run(&crate::optimize(parsed), &[])?;
+    if output != bytes {
+        return Err(BrainfcError::Runtime(format!(
+            "generated program prints {:?} instead of {:?}",
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(bytes)
+        )));
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Generates a program for `bytes` and runs it unoptimized
+    fn round_trip(bytes: &[u8]) {
+        let program = print_program(bytes).unwrap();
+        let parsed = crate::parse(crate::lex(&program)).unwrap();
+        let output = crate::interpreter::run(&parsed, &[]).unwrap();
+        assert_eq!(output, bytes, "{program}");
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(b"");
+        round_trip(b"Hello, World!\n");
+        round_trip(&[0, 0, 255, 1, 128, 127, 0]);
+        round_trip(b"caf\xe9 \xff\xfe not UTF-8");
+        round_trip(&(0..=255).collect::<Vec<u8>>());
+        round_trip(
+            &(0..=255)
+                .rev()
+                .cycle()
+                .step_by(7)
+                .take(1000)
+                .collect::<Vec<u8>>(),
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use brainfc::{BrainfcError, CompileOptions};
+use colored::Colorize;
+
+use crate::app::{self, TestArgs};
+
+// Lines of a diff shown for a failing test
+const MAX_DIFF_LINES: usize = 10;
+
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skip(&'static str),
+}
+
+/// Runs `executable` with `input` on stdin, killing it after `timeout`.
+/// Returns its stdout, or `None` on timeout.
+fn run_with_timeout(
+    executable: &Path,
+    input: Vec<u8>,
+    timeout: Duration,
+) -> Result<Option<(std::process::ExitStatus, Vec<u8>)>, BrainfcError> {
+    let mut child = Command::new(executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| BrainfcError::io(format!("Could not run {executable:?}"), e))?;
+
+    // Feed and drain the pipes on threads, so neither side blocks on a full pipe
+    let mut stdin = child.stdin.take().unwrap();
+    std::thread::spawn(move || stdin.write_all(&input));
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .map_err(|e| BrainfcError::io(format!("Could not wait for {executable:?}"), e))?
+        {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    let output = reader
+        .join()
+        .unwrap()
+        .map_err(|e| BrainfcError::io(format!("Could not read the output of {executable:?}"), e))?;
+    Ok(Some((status, output)))
+}
+
+/// The first lines in which `actual` differs from `expected`
+fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let expected: Vec<&str> = expected.split_inclusive('\n').collect();
+    let actual: Vec<&str> = actual.split_inclusive('\n').collect();
+
+    let mut lines = Vec::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (want, got) = (expected.get(i), actual.get(i));
+        if want == got {
+            continue;
+        }
+        if lines.len() >= MAX_DIFF_LINES {
+            lines.push("    ...".dimmed().to_string());
+            break;
+        }
+        if let Some(want) = want {
+            lines.push(format!("    {:>4} - {want:?}", i + 1).red().to_string());
+        }
+        if let Some(got) = got {
+            lines.push(format!("    {:>4} + {got:?}", i + 1).green().to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+fn run_test(program: &Path, args: &TestArgs, out_dir: &Path) -> Result<Outcome, BrainfcError> {
+    let Ok(expected) = std::fs::read(program.with_extension("out")) else {
+        return Ok(Outcome::Skip("no .out file"));
+    };
+    let input = match std::fs::read(program.with_extension("in")) {
+        Ok(input) => input,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(BrainfcError::io(format!("Could not open {program:?}"), e)),
+    };
+
+    let compiled = app::read_source(program, &args.extensions).and_then(|(source, source_map)| {
+        let (parsed, inline_input) =
+            app::parse_source(program, &source, args.dialect.as_deref(), &args.extensions)
+                .map_err(|e| match &source_map {
+                    Some(map) => map.map_error(e),
+                    None => e,
+                })?;
+        let mut options = CompileOptions::new()
+            .backend(args.backend)
+            .output(out_dir.join(program.file_stem().unwrap()));
+        if let Some(input) = inline_input {
+            options = options.embedded_input(input);
+        }
+        brainfc::compile_to_executable(brainfc::optimize(parsed), &options)
+    });
+    let executable = match compiled {
+        Ok(executable) => executable,
+        Err(e) => return Ok(Outcome::Fail(e.to_string())),
+    };
+
+    let result = run_with_timeout(&executable, input, Duration::from_secs(args.timeout))?;
+    let _ = std::fs::remove_file(&executable);
+    let Some((status, output)) = result else {
+        return Ok(Outcome::Fail(format!("timed out after {}s", args.timeout)));
+    };
+    if output != expected {
+        return Ok(Outcome::Fail(format!(
+            "output differs from the .out file\n{}",
+            diff(&expected, &output)
+        )));
+    }
+    if !status.success() {
+        return Ok(Outcome::Fail(format!("exited with {status}")));
+    }
+    Ok(Outcome::Pass)
+}
+
+/// The programs given on the command line and the `*.b` files of the
+/// directories given, sorted by name within each directory
+fn programs(paths: &[PathBuf]) -> Result<Vec<PathBuf>, BrainfcError> {
+    let mut programs = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            programs.push(path.clone());
+            continue;
+        }
+        let mut found: Vec<PathBuf> = std::fs::read_dir(path)
+            .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("b"))
+            .collect();
+        found.sort();
+        programs.extend(found);
+    }
+    Ok(programs)
+}
+
+pub fn run(args: TestArgs) -> Result<(), BrainfcError> {
+    let programs = programs(&args.paths)?;
+
+    let out_dir = std::env::temp_dir().join(format!("brainfc-test-{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir)
+        .map_err(|e| BrainfcError::io(format!("Could not create {out_dir:?}"), e))?;
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for program in &programs {
+        let name = program.file_stem().unwrap().to_string_lossy();
+        let start = Instant::now();
+        match run_test(program, &args, &out_dir)? {
+            Outcome::Pass => {
+                passed += 1;
+                let time = format!("({:.2}s)", start.elapsed().as_secs_f64());
+                println!("{} {name} {}", "PASS".green().bold(), time.dimmed());
+            }
+            Outcome::Fail(reason) => {
+                failed += 1;
+                println!("{} {name}: {reason}", "FAIL".red().bold());
+            }
+            Outcome::Skip(reason) => {
+                skipped += 1;
+                println!("{} {name}: {reason}", "SKIP".yellow().bold());
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&out_dir);
+
+    let summary = format!("{passed} passed, {failed} failed, {skipped} skipped");
+    if failed > 0 {
+        println!("\n{}", summary.red().bold());
+        return Err(BrainfcError::Runtime(format!(
+            "{failed} of {} tests failed",
+            programs.len()
+        )));
+    }
+    println!("\n{}", summary.green().bold());
+    Ok(())
+}
@@ -0,0 +1,358 @@
+//! Reference interpreter over a parsed (and optionally optimized) `Program`.
+//! Semantics match the compiled code: 8-bit wrapping cells, a tape of
+//! `cdylib::TAPE_SIZE` cells and 255 stored on EOF.
+
+use crate::cdylib::TAPE_SIZE;
+use crate::error::{BrainfcError, Result};
+use crate::lexer::{Op, Pos};
+use crate::parser::{Block, Program};
+
+/// A flattened instruction. Loops become a pair of conditional jumps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instr {
+    Move(i16),
+    Modify(i16),
+    Inp(u16),
+    Outp(u16),
+    /// `[`: jumps past the matching `LoopEnd` if the current cell is zero
+    LoopStart {
+        end: usize,
+    },
+    /// `]`: jumps back past the matching `LoopStart` unless the current cell is zero
+    LoopEnd {
+        start: usize,
+    },
+    Reset,
+    Multiply(Vec<(i32, i32)>),
+    /// `#` of `Extension::DebugDump`, does nothing here
+    DebugDump,
+    /// `(`: makes the following instructions the procedure numbered by the
+    /// current cell and jumps past the matching `Return`
+    Define {
+        end: usize,
+    },
+    /// `)`: returns to the instruction after the last `Call`
+    Return,
+    /// `:`: calls the procedure numbered by the current cell, if one is defined
+    Call,
+    /// `Y`: starts a thread with a copy of the tape, see `Interpreter`
+    Fork,
+}
+
+struct Flattener {
+    code: Vec<Instr>,
+    positions: Vec<Pos>,
+}
+
+impl Flattener {
+    fn push(&mut self, instr: Instr, pos: Pos) {
+        self.code.push(instr);
+        self.positions.push(pos);
+    }
+
+    fn flatten(&mut self, program: &Program) {
+        for block in program {
+            match block {
+                Block::Simple(tokens) => {
+                    for token in tokens {
+                        let instr = match token.op {
+                            Op::Move(x) => Instr::Move(x),
+                            Op::Modify(x) => Instr::Modify(x),
+                            Op::Inp(x) => Instr::Inp(x),
+                            Op::Outp(x) => Instr::Outp(x),
+                            Op::DebugDump => Instr::DebugDump,
+                            Op::Call => Instr::Call,
+                            Op::Fork => Instr::Fork,
+                            Op::LBr | Op::RBr | Op::ProcStart | Op::ProcEnd => unreachable!(),
+                        };
+                        self.push(instr, token.pos);
+                    }
+                }
+                Block::Loop(body, span) => {
+                    let start = self.code.len();
+                    self.push(Instr::LoopStart { end: 0 }, span.start);
+                    self.flatten(body);
+                    let end = self.code.len();
+                    self.push(Instr::LoopEnd { start }, span.end);
+                    self.code[start] = Instr::LoopStart { end };
+                }
+                Block::Procedure(body, span) => {
+                    let start = self.code.len();
+                    self.push(Instr::Define { end: 0 }, span.start);
+                    self.flatten(body);
+                    let end = self.code.len();
+                    self.push(Instr::Return, span.end);
+                    self.code[start] = Instr::Define { end };
+                }
+                Block::Reset { span, .. } => self.push(Instr::Reset, span.start),
+                Block::Multiply { ops, span } => {
+                    self.push(Instr::Multiply(ops.clone()), span.start)
+                }
+                Block::JmpLoop { .. } => {}
+            }
+        }
+    }
+}
+
+/// State of one thread of a brainfork program, the only one of any other
+#[derive(Clone)]
+struct Thread {
+    tape: Vec<u8>,
+    pointer: usize,
+    pc: usize,
+    /// First instruction of every defined procedure
+    procedures: Vec<Option<usize>>,
+    /// Return addresses of the running procedure calls
+    calls: Vec<usize>,
+}
+
+impl Thread {
+    fn cell(&self) -> u8 {
+        self.tape[self.pointer]
+    }
+
+    fn cell_mut(&mut self) -> &mut u8 {
+        &mut self.tape[self.pointer]
+    }
+}
+
+/// Runs threads forked by `Y` round-robin, one instruction at a time, in
+/// the order they were created. They share input and output.
+pub struct Interpreter {
+    code: Vec<Instr>,
+    positions: Vec<Pos>,
+    threads: Vec<Thread>,
+    current: usize,
+    input: Vec<u8>,
+    input_pos: usize,
+    output: Vec<u8>,
+    steps: u64,
+}
+
+impl Interpreter {
+    pub fn new(program: &Program) -> Self {
+        let mut flattener = Flattener {
+            code: Vec::new(),
+            positions: Vec::new(),
+        };
+        flattener.flatten(program);
+
+        Interpreter {
+            code: flattener.code,
+            positions: flattener.positions,
+            threads: vec![Thread {
+                tape: vec![0; TAPE_SIZE],
+                pointer: 0,
+                pc: 0,
+                procedures: vec![None; 256],
+                calls: Vec::new(),
+            }],
+            current: 0,
+            input: Vec::new(),
+            input_pos: 0,
+            output: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    /// Bytes read by `,`, which stores 255 once they are used up
+    pub fn with_input(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.input = input.into();
+        self
+    }
+
+    pub fn code(&self) -> &[Instr] {
+        &self.code
+    }
+
+    /// Source position of every instruction in `code`
+    pub fn positions(&self) -> &[Pos] {
+        &self.positions
+    }
+
+    fn thread(&self) -> &Thread {
+        &self.threads[self.current]
+    }
+
+    /// Index of the next instruction, `code().len()` once finished
+    pub fn pc(&self) -> usize {
+        self.thread().pc
+    }
+
+    /// Source position of the next instruction
+    pub fn pos(&self) -> Option<Pos> {
+        self.positions.get(self.pc()).copied()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.threads
+            .iter()
+            .all(|thread| thread.pc >= self.code.len())
+    }
+
+    /// Number of threads, finished ones included
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Index of the thread that runs next
+    pub fn current_thread(&self) -> usize {
+        self.current
+    }
+
+    /// Tape of the thread that runs next
+    pub fn tape(&self) -> &[u8] {
+        &self.thread().tape
+    }
+
+    /// Tape of the thread with index `thread`, if it was forked already
+    pub fn thread_tape(&self, thread: usize) -> Option<&[u8]> {
+        self.threads.get(thread).map(|thread| &thread.tape[..])
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.thread().pointer
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Returns the output produced since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn cell_index(&self, diff: i64) -> Result<usize> {
+        let index = self.pointer() as i64 + diff;
+        if index < 0 || index >= TAPE_SIZE as i64 {
+            let pos = self.pos().unwrap_or_default();
+            return Err(
+                BrainfcError::Runtime(format!("tape pointer out of bounds ({index})")).at(pos),
+            );
+        }
+        Ok(index as usize)
+    }
+
+    fn read(&mut self) -> u8 {
+        match self.input.get(self.input_pos) {
+            Some(&byte) => {
+                self.input_pos += 1;
+                byte
+            }
+            None => u8::MAX,
+        }
+    }
+
+    /// Executes the next instruction of the current thread and switches to
+    /// the next unfinished thread. Returns `false` if the program had
+    /// already finished.
+    pub fn step(&mut self) -> Result<bool> {
+        let Some(instr) = self.code.get(self.pc()) else {
+            return Ok(false);
+        };
+
+        let mut next_pc = self.pc() + 1;
+        match *instr {
+            Instr::Move(x) => self.threads[self.current].pointer = self.cell_index(x as i64)?,
+            Instr::Modify(x) => {
+                let cell = self.threads[self.current].cell_mut();
+                *cell = cell.wrapping_add(x as u8)
+            }
+            Instr::Inp(x) => {
+                for _ in 0..x {
+                    let byte = self.read();
+                    *self.threads[self.current].cell_mut() = byte;
+                }
+            }
+            Instr::Outp(x) => {
+                for _ in 0..x {
+                    self.output.push(self.thread().cell());
+                }
+            }
+            Instr::LoopStart { end } => {
+                if self.thread().cell() == 0 {
+                    next_pc = end + 1;
+                }
+            }
+            Instr::LoopEnd { start } => {
+                if self.thread().cell() != 0 {
+                    next_pc = start + 1;
+                }
+            }
+            Instr::Reset => *self.threads[self.current].cell_mut() = 0,
+            // Like the loop it replaces, touches no other cell if the counter is zero
+            Instr::Multiply(_) if self.thread().cell() == 0 => {}
+            Instr::Multiply(ref ops) => {
+                let base = self.thread().cell();
+                for &(diff, multiply_val) in ops {
+                    let index = self.cell_index(diff as i64)?;
+                    let product = base.wrapping_mul(multiply_val as u8);
+                    let tape = &mut self.threads[self.current].tape;
+                    tape[index] = tape[index].wrapping_add(product);
+                }
+                *self.threads[self.current].cell_mut() = 0;
+            }
+            Instr::DebugDump => {}
+            Instr::Define { end } => {
+                let thread = &mut self.threads[self.current];
+                let cell = thread.cell() as usize;
+                thread.procedures[cell] = Some(thread.pc + 1);
+                next_pc = end + 1;
+            }
+            Instr::Return => next_pc = self.threads[self.current].calls.pop().unwrap_or(next_pc),
+            Instr::Call => {
+                let thread = &mut self.threads[self.current];
+                let cell = thread.cell() as usize;
+                if let Some(start) = thread.procedures[cell] {
+                    thread.calls.push(next_pc);
+                    next_pc = start;
+                }
+            }
+            Instr::Fork => {
+                let pointer = self.cell_index(1)?;
+                let mut child = self.thread().clone();
+                child.pointer = pointer;
+                *child.cell_mut() = 1;
+                child.pc = next_pc;
+                *self.threads[self.current].cell_mut() = 0;
+                self.threads.push(child);
+            }
+        }
+
+        self.threads[self.current].pc = next_pc;
+        self.steps += 1;
+        self.switch_thread();
+        Ok(true)
+    }
+
+    /// Moves on to the next unfinished thread after the current one,
+    /// staying on the current one if it is the only one left
+    fn switch_thread(&mut self) {
+        let len = self.threads.len();
+        if let Some(next) = (1..=len)
+            .map(|offset| (self.current + offset) % len)
+            .find(|&i| self.threads[i].pc < self.code.len())
+        {
+            self.current = next;
+        }
+    }
+
+    /// Runs until the program finishes
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? {}
+        Ok(())
+    }
+}
+
+/// Runs `program` on `input` and returns its output
+pub fn run(program: &Program, input: &[u8]) -> Result<Vec<u8>> {
+    let mut interpreter = Interpreter::new(program).with_input(input);
+    interpreter.run()?;
+    Ok(interpreter.take_output())
+}
@@ -0,0 +1,111 @@
+//! A small structured language that compiles to a `Program`, so it goes
+//! through the optimizer and the backends like brainfuck does:
+//!
+//! ```text
+//! var n = 5;
+//! while n != 0 {
+//!     print '0' + n, "\n";
+//!     n -= 1;
+//! }
+//! ```
+//!
+//! Variables are global and hold a byte. Expressions have `+`, `-`, `*`,
+//! `==`, `!=`, `!`, unary `-`, numbers and character literals and wrap
+//! around like cells do; conditions are true unless zero. Statements are
+//! `var`, assignments with `=`, `+=` and `-=`, `if`/`else`, `while`, `print`
+//! of strings and expressions, and `read`.
+
+mod ast;
+mod codegen;
+mod syntax;
+
+use std::path::Path;
+
+use crate::error::Result;
+use crate::parser::Program;
+
+/// Whether `path` is a source file of this language, `*.bfl`
+pub fn is_source(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("bfl")
+}
+
+pub fn compile(source: &str) -> Result<Program> {
+    let stmts = syntax::parse(source)?;
+    let mut codegen = codegen::Codegen::new();
+    for stmt in &stmts {
+        codegen.stmt(stmt)?;
+    }
+    Ok(codegen.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// What `source` prints, which must not change by optimizing it
+    fn run(source: &str, input: &[u8]) -> Vec<u8> {
+        let output = crate::interpreter::run(&compile(source).unwrap(), input).unwrap();
+        let optimized = crate::optimize(compile(source).unwrap());
+        assert_eq!(crate::interpreter::run(&optimized, input).unwrap(), output);
+        output
+    }
+
+    fn error(source: &str) -> String {
+        compile(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn countdown() {
+        let source = r#"
+var n = 5;
+while n != 0 {
+    print '0' + n, " ";
+    n -= 1;
+}
+if n == 0 { print "liftoff\n"; } else { print "?\n"; }
+"#;
+        assert_eq!(run(source, b""), b"5 4 3 2 1 liftoff\n");
+    }
+
+    #[test]
+    fn expressions() {
+        let source = "var a = 200 + 100; var b = 3 * -2; a += b; \
+                      print a, b, !0, !a, a == 38, a != 38, (1 + 2) * 3;";
+        assert_eq!(run(source, b""), [38, 250, 1, 0, 1, 0, 9]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = r#"
+var c;
+read c;
+while c != 0 {
+    if c == 'a' { print "A"; } else if c == 'b' { print 'B'; } else { print c; }
+    read c;
+}
+"#;
+        assert_eq!(run(source, b"abc\0"), b"ABc");
+    }
+
+    #[test]
+    fn long_outputs() {
+        // More repeated bytes than one output operation can count
+        let text = "x".repeat(u16::MAX as usize + 10);
+        let output = run(&format!("print \"{text}\";"), b"");
+        assert_eq!(output, text.as_bytes());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("var a;\nvar a;"),
+            "parse error: Variable `a` is already defined at 2:1"
+        );
+        assert_eq!(
+            error("var a = b;"),
+            "parse error: Undefined variable `b` at 1:9"
+        );
+        assert!(error("var a = ;").starts_with("parse error: "));
+        assert!(error("while 1 { print 1;").starts_with("parse error: "));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::lexer::Pos;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Number(u8),
+    Var(String),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub enum PrintArg {
+    Str(Vec<u8>),
+    Expr(Expr),
+}
+
+/// Statements in braces and the position of the closing brace
+#[derive(Debug)]
+pub struct Body {
+    pub stmts: Vec<Stmt>,
+    pub end: Pos,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    Var(String, Option<Expr>),
+    /// `x = e`, `x += e` and `x -= e`, the latter two with their operator
+    Assign(String, Option<BinOp>, Expr),
+    If(Expr, Body, Option<Body>),
+    While(Expr, Body),
+    Print(Vec<PrintArg>),
+    Read(String),
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub pos: Pos,
+}
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+
+use crate::cdylib::TAPE_SIZE;
+use crate::error::{BrainfcError, Result};
+use crate::lang::ast::*;
+use crate::lexer::{Op, Pos, Token};
+use crate::parser::{Block, Program, Span};
+
+/// Emits brainfuck for the statements of a program. Variables and
+/// temporaries are cells; a free cell is always zero, so freeing a temporary
+/// requires clearing it first. The tape pointer is known at every point since
+/// every loop ends on the cell it started on.
+pub struct Codegen {
+    /// The program being built and the bodies of the loops it is in
+    programs: Vec<Program>,
+    pointer: usize,
+    used: Vec<bool>,
+    vars: HashMap<String, usize>,
+    /// Position of the statement or expression being compiled
+    pos: Pos,
+}
+
+impl Codegen {
+    pub fn new() -> Self {
+        Codegen {
+            programs: vec![Program::new()],
+            pointer: 0,
+            used: Vec::new(),
+            vars: HashMap::new(),
+            pos: Pos::default(),
+        }
+    }
+
+    fn error(&self, msg: impl std::fmt::Display) -> BrainfcError {
+        BrainfcError::Parse(msg.to_string()).at(self.pos)
+    }
+
+    /// Appends `op`, merging it into the previous operation if both move,
+    /// modify or output and the sum fits
+    fn emit(&mut self, op: Op) {
+        let program = self.programs.last_mut().unwrap();
+        if !matches!(program.last(), Some(Block::Simple(_))) {
+            program.push(Block::Simple(Vec::new()));
+        }
+        let Some(Block::Simple(tokens)) = program.last_mut() else {
+            unreachable!()
+        };
+
+        let merged = match (tokens.last().map(|token| &token.op), &op) {
+            (Some(Op::Move(x)), Op::Move(y)) => x.checked_add(*y).map(Op::Move),
+            (Some(Op::Modify(x)), Op::Modify(y)) => Some(Op::Modify((x + y) as i8 as i16)),
+            (Some(Op::Outp(x)), Op::Outp(y)) => x.checked_add(*y).map(Op::Outp),
+            _ => None,
+        };
+        match merged {
+            Some(Op::Move(0) | Op::Modify(0)) => {
+                tokens.pop();
+            }
+            Some(op) => tokens.last_mut().unwrap().op = op,
+            None => tokens.push(Token { op, pos: self.pos }),
+        }
+        let empty = tokens.is_empty();
+        if empty {
+            program.pop();
+        }
+    }
+
+    fn goto(&mut self, cell: usize) {
+        let diff = cell as i16 - self.pointer as i16;
+        if diff != 0 {
+            self.emit(Op::Move(diff));
+        }
+        self.pointer = cell;
+    }
+
+    fn add(&mut self, cell: usize, value: i32) {
+        self.goto(cell);
+        if value as u8 != 0 {
+            self.emit(Op::Modify(value as i8 as i16));
+        }
+    }
+
+    /// `[...]` on `cell`, running `body` with the pointer on `cell`
+    fn loop_at(
+        &mut self,
+        cell: usize,
+        end: Pos,
+        body: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        self.goto(cell);
+        let start = self.pos;
+        self.programs.push(Program::new());
+        body(self)?;
+        self.goto(cell);
+        let body = self.programs.pop().unwrap();
+        self.programs
+            .last_mut()
+            .unwrap()
+            .push(Block::Loop(body, Span { start, end }));
+        Ok(())
+    }
+
+    fn clear(&mut self, cell: usize) -> Result<()> {
+        let end = self.pos;
+        self.loop_at(cell, end, |gen| {
+            gen.add(cell, -1);
+            Ok(())
+        })
+    }
+
+    /// Adds `factor` times `src` to each target and clears `src`
+    fn move_to(&mut self, src: usize, targets: &[(usize, i32)]) -> Result<()> {
+        let end = self.pos;
+        self.loop_at(src, end, |gen| {
+            gen.add(src, -1);
+            for &(target, factor) in targets {
+                gen.add(target, factor);
+            }
+            Ok(())
+        })
+    }
+
+    fn alloc(&mut self) -> Result<usize> {
+        let cell = match self.used.iter().position(|used| !used) {
+            Some(cell) => cell,
+            None => {
+                self.used.push(false);
+                self.used.len() - 1
+            }
+        };
+        if cell >= TAPE_SIZE {
+            return Err(self.error("Out of cells"));
+        }
+        self.used[cell] = true;
+        Ok(cell)
+    }
+
+    /// Returns a cleared cell to the allocator
+    fn free(&mut self, cell: usize) {
+        self.used[cell] = false;
+    }
+
+    fn var(&self, name: &str) -> Result<usize> {
+        self.vars
+            .get(name)
+            .copied()
+            .ok_or_else(|| self.error(format!("Undefined variable `{name}`")))
+    }
+
+    /// Evaluates `expr` into a new temporary
+    fn expr(&mut self, expr: &Expr) -> Result<usize> {
+        self.pos = expr.pos;
+        match &expr.kind {
+            ExprKind::Number(n) => {
+                let cell = self.alloc()?;
+                self.add(cell, *n as i32);
+                Ok(cell)
+            }
+            ExprKind::Var(name) => {
+                let var = self.var(name)?;
+                let cell = self.alloc()?;
+                let tmp = self.alloc()?;
+                self.move_to(var, &[(cell, 1), (tmp, 1)])?;
+                self.move_to(tmp, &[(var, 1)])?;
+                self.free(tmp);
+                Ok(cell)
+            }
+            ExprKind::Not(operand) => {
+                let value = self.expr(operand)?;
+                self.pos = expr.pos;
+                self.not(value)
+            }
+            ExprKind::Neg(operand) => {
+                let value = self.expr(operand)?;
+                self.pos = expr.pos;
+                let cell = self.alloc()?;
+                self.move_to(value, &[(cell, -1)])?;
+                self.free(value);
+                Ok(cell)
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.pos = expr.pos;
+                self.binary(*op, lhs, rhs)
+            }
+        }
+    }
+
+    /// 1 if `value` is zero, else 0. Consumes `value`.
+    fn not(&mut self, value: usize) -> Result<usize> {
+        let cell = self.alloc()?;
+        self.add(cell, 1);
+        let end = self.pos;
+        self.loop_at(value, end, |gen| {
+            gen.clear(value)?;
+            gen.add(cell, -1);
+            Ok(())
+        })?;
+        self.free(value);
+        Ok(cell)
+    }
+
+    /// Consumes both operands
+    fn binary(&mut self, op: BinOp, lhs: usize, rhs: usize) -> Result<usize> {
+        match op {
+            BinOp::Add | BinOp::Sub => {
+                let factor = if op == BinOp::Add { 1 } else { -1 };
+                self.move_to(rhs, &[(lhs, factor)])?;
+                self.free(rhs);
+                Ok(lhs)
+            }
+            BinOp::Mul => {
+                let product = self.alloc()?;
+                let tmp = self.alloc()?;
+                let end = self.pos;
+                self.loop_at(lhs, end, |gen| {
+                    gen.add(lhs, -1);
+                    gen.move_to(rhs, &[(product, 1), (tmp, 1)])?;
+                    gen.move_to(tmp, &[(rhs, 1)])
+                })?;
+                self.clear(rhs)?;
+                self.free(lhs);
+                self.free(rhs);
+                self.free(tmp);
+                Ok(product)
+            }
+            BinOp::Eq => {
+                let difference = self.binary(BinOp::Sub, lhs, rhs)?;
+                self.not(difference)
+            }
+            BinOp::Ne => {
+                let difference = self.binary(BinOp::Sub, lhs, rhs)?;
+                let cell = self.alloc()?;
+                let end = self.pos;
+                self.loop_at(difference, end, |gen| {
+                    gen.clear(difference)?;
+                    gen.add(cell, 1);
+                    Ok(())
+                })?;
+                self.free(difference);
+                Ok(cell)
+            }
+        }
+    }
+
+    fn body(&mut self, body: &Body) -> Result<()> {
+        body.stmts.iter().try_for_each(|stmt| self.stmt(stmt))
+    }
+
+    pub fn stmt(&mut self, stmt: &Stmt) -> Result<()> {
+        self.pos = stmt.pos;
+        match &stmt.kind {
+            StmtKind::Var(name, value) => {
+                if self.vars.contains_key(name) {
+                    return Err(self.error(format!("Variable `{name}` is already defined")));
+                }
+                let var = self.alloc()?;
+                if self.programs.len() > 1 {
+                    // Declared in a loop, the cell keeps its value from the last iteration
+                    self.clear(var)?;
+                }
+                if let Some(value) = value {
+                    let value = self.expr(value)?;
+                    self.move_to(value, &[(var, 1)])?;
+                    self.free(value);
+                }
+                self.vars.insert(name.clone(), var);
+            }
+            StmtKind::Assign(name, op, value) => {
+                let var = self.var(name)?;
+                let value = self.expr(value)?;
+                self.pos = stmt.pos;
+                let factor = match op {
+                    None => {
+                        self.clear(var)?;
+                        1
+                    }
+                    Some(BinOp::Sub) => -1,
+                    Some(_) => 1,
+                };
+                self.move_to(value, &[(var, factor)])?;
+                self.free(value);
+            }
+            StmtKind::If(cond, then, otherwise) => {
+                let cond = self.expr(cond)?;
+                self.pos = stmt.pos;
+                let run_otherwise = match otherwise {
+                    Some(_) => {
+                        let cell = self.alloc()?;
+                        self.add(cell, 1);
+                        Some(cell)
+                    }
+                    None => None,
+                };
+                self.loop_at(cond, then.end, |gen| {
+                    gen.clear(cond)?;
+                    if let Some(cell) = run_otherwise {
+                        gen.add(cell, -1);
+                    }
+                    gen.body(then)
+                })?;
+                self.free(cond);
+                if let (Some(cell), Some(otherwise)) = (run_otherwise, otherwise) {
+                    self.pos = stmt.pos;
+                    self.loop_at(cell, otherwise.end, |gen| {
+                        gen.add(cell, -1);
+                        gen.body(otherwise)
+                    })?;
+                    self.free(cell);
+                }
+            }
+            StmtKind::While(cond_expr, body) => {
+                let cond = self.expr(cond_expr)?;
+                self.pos = stmt.pos;
+                self.loop_at(cond, body.end, |gen| {
+                    gen.clear(cond)?;
+                    gen.body(body)?;
+                    let next = gen.expr(cond_expr)?;
+                    gen.move_to(next, &[(cond, 1)])?;
+                    gen.free(next);
+                    Ok(())
+                })?;
+                self.free(cond);
+            }
+            StmtKind::Print(args) => {
+                for arg in args {
+                    match arg {
+                        PrintArg::Str(bytes) => {
+                            let cell = self.alloc()?;
+                            let mut current = 0;
+                            for &byte in bytes {
+                                self.add(cell, byte as i32 - current);
+                                self.emit(Op::Outp(1));
+                                current = byte as i32;
+                            }
+                            self.add(cell, -current);
+                            self.free(cell);
+                        }
+                        PrintArg::Expr(expr) => {
+                            let cell = self.expr(expr)?;
+                            self.goto(cell);
+                            self.emit(Op::Outp(1));
+                            self.clear(cell)?;
+                            self.free(cell);
+                        }
+                    }
+                    self.pos = stmt.pos;
+                }
+            }
+            StmtKind::Read(name) => {
+                let var = self.var(name)?;
+                self.goto(var);
+                self.emit(Op::Inp(1));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Program {
+        self.programs.pop().unwrap()
+    }
+}
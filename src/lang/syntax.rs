@@ -0,0 +1,390 @@
+use crate::error::{BrainfcError, Result};
+use crate::lang::ast::*;
+use crate::lexer::Pos;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Tok {
+    Ident(String),
+    Number(u32),
+    Str(Vec<u8>),
+    Punct(&'static str),
+    Eof,
+}
+
+impl std::fmt::Display for Tok {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tok::Ident(name) => write!(f, "`{name}`"),
+            Tok::Number(n) => write!(f, "`{n}`"),
+            Tok::Str(_) => write!(f, "a string"),
+            Tok::Punct(p) => write!(f, "`{p}`"),
+            Tok::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+// Longest first, so `==` wins over `=`
+const PUNCTS: [&str; 15] = [
+    "==", "!=", "+=", "-=", "=", "+", "-", "*", "!", "(", ")", "{", "}", ";", ",",
+];
+
+const KEYWORDS: [&str; 6] = ["var", "if", "else", "while", "print", "read"];
+
+fn error(pos: Pos, msg: impl std::fmt::Display) -> BrainfcError {
+    BrainfcError::Parse(msg.to_string()).at(pos)
+}
+
+struct Tokenizer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    pos: Pos,
+}
+
+impl Tokenizer<'_> {
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
+    }
+
+    fn escape(&mut self, start: Pos) -> Result<u8> {
+        let byte = match self.bump() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let digits: String = [self.bump(), self.bump()].into_iter().flatten().collect();
+                u8::from_str_radix(&digits, 16)
+                    .map_err(|_| error(start, format!("Invalid escape `\\x{digits}`")))?
+            }
+            c => {
+                return Err(error(
+                    start,
+                    format!("Invalid escape `\\{}`", c.unwrap_or(' ')),
+                ))
+            }
+        };
+        Ok(byte)
+    }
+
+    /// The bytes of a string or character literal, after its opening quote
+    fn literal(&mut self, quote: char, start: Pos) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.bump() {
+                None => return Err(error(start, "Unterminated literal")),
+                Some('\\') => bytes.push(self.escape(start)?),
+                Some(c) if c == quote => return Ok(bytes),
+                Some(c) => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<(Tok, Pos)> {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('/') => {
+                    let pos = self.pos;
+                    self.bump();
+                    if self.bump() != Some('/') {
+                        return Err(error(pos, "Unexpected `/`"));
+                    }
+                    while !matches!(self.bump(), Some('\n') | None) {}
+                }
+                _ => break,
+            }
+        }
+
+        let pos = self.pos;
+        let Some(&c) = self.chars.peek() else {
+            return Ok((Tok::Eof, pos));
+        };
+        let tok = if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = self
+                .chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                name.push(c);
+                self.bump();
+            }
+            Tok::Ident(name)
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(c);
+                self.bump();
+            }
+            Tok::Number(digits.parse().unwrap_or(u32::MAX))
+        } else if c == '"' {
+            self.bump();
+            Tok::Str(self.literal('"', pos)?)
+        } else if c == '\'' {
+            self.bump();
+            match self.literal('\'', pos)?[..] {
+                [byte] => Tok::Number(byte as u32),
+                _ => return Err(error(pos, "A character literal must be a single byte")),
+            }
+        } else {
+            let rest: String = self.chars.clone().take(2).collect();
+            let Some(punct) = PUNCTS.iter().find(|p| rest.starts_with(**p)) else {
+                return Err(error(pos, format!("Unexpected `{c}`")));
+            };
+            for _ in 0..punct.len() {
+                self.bump();
+            }
+            Tok::Punct(punct)
+        };
+        Ok((tok, pos))
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Tok, Pos)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.next].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.next].1
+    }
+
+    fn advance(&mut self) -> (Tok, Pos) {
+        let token = self.tokens[self.next].clone();
+        if self.next + 1 < self.tokens.len() {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Tok::Punct(p) if *p == punct) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<Pos> {
+        let pos = self.pos();
+        if !self.eat(punct) {
+            return Err(error(
+                pos,
+                format!("Expected `{punct}`, found {}", self.peek()),
+            ));
+        }
+        Ok(pos)
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.advance() {
+            (Tok::Ident(name), _) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            (tok, pos) => Err(error(pos, format!("Expected a variable name, found {tok}"))),
+        }
+    }
+
+    fn body(&mut self) -> Result<Body> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !matches!(self.peek(), Tok::Punct("}") | Tok::Eof) {
+            stmts.push(self.stmt()?);
+        }
+        let end = self.expect("}")?;
+        Ok(Body { stmts, end })
+    }
+
+    fn stmt(&mut self) -> Result<Stmt> {
+        let pos = self.pos();
+        let keyword = match self.peek() {
+            Tok::Ident(name) if KEYWORDS.contains(&name.as_str()) => name.clone(),
+            _ => {
+                let name = self.ident()?;
+                let op = match self.advance() {
+                    (Tok::Punct("="), _) => None,
+                    (Tok::Punct("+="), _) => Some(BinOp::Add),
+                    (Tok::Punct("-="), _) => Some(BinOp::Sub),
+                    (tok, pos) => {
+                        return Err(error(
+                            pos,
+                            format!("Expected `=`, `+=` or `-=`, found {tok}"),
+                        ))
+                    }
+                };
+                let value = self.expr()?;
+                self.expect(";")?;
+                return Ok(Stmt {
+                    kind: StmtKind::Assign(name, op, value),
+                    pos,
+                });
+            }
+        };
+        self.advance();
+
+        let kind = match keyword.as_str() {
+            "var" => {
+                let name = self.ident()?;
+                let value = match self.eat("=") {
+                    true => Some(self.expr()?),
+                    false => None,
+                };
+                self.expect(";")?;
+                StmtKind::Var(name, value)
+            }
+            "if" => {
+                let cond = self.expr()?;
+                let then = self.body()?;
+                let otherwise = if self.peek() == &Tok::Ident("else".to_string()) {
+                    self.advance();
+                    if self.peek() == &Tok::Ident("if".to_string()) {
+                        let stmt = self.stmt()?;
+                        let end = stmt.pos;
+                        Some(Body {
+                            stmts: vec![stmt],
+                            end,
+                        })
+                    } else {
+                        Some(self.body()?)
+                    }
+                } else {
+                    None
+                };
+                StmtKind::If(cond, then, otherwise)
+            }
+            "while" => {
+                let cond = self.expr()?;
+                StmtKind::While(cond, self.body()?)
+            }
+            "print" => {
+                let mut args = Vec::new();
+                loop {
+                    let arg = match self.peek() {
+                        Tok::Str(bytes) => {
+                            let bytes = bytes.clone();
+                            self.advance();
+                            PrintArg::Str(bytes)
+                        }
+                        _ => PrintArg::Expr(self.expr()?),
+                    };
+                    args.push(arg);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(";")?;
+                StmtKind::Print(args)
+            }
+            "read" => {
+                let name = self.ident()?;
+                self.expect(";")?;
+                StmtKind::Read(name)
+            }
+            _ => return Err(error(pos, format!("Unexpected `{keyword}`"))),
+        };
+        Ok(Stmt { kind, pos })
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.additive()?;
+        loop {
+            let op = match self.peek() {
+                Tok::Punct("==") => BinOp::Eq,
+                Tok::Punct("!=") => BinOp::Ne,
+                _ => return Ok(lhs),
+            };
+            lhs = self.binary(op, lhs, Self::additive)?;
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Tok::Punct("+") => BinOp::Add,
+                Tok::Punct("-") => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            lhs = self.binary(op, lhs, Self::term)?;
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Tok::Punct("*") = self.peek() {
+            lhs = self.binary(BinOp::Mul, lhs, Self::unary)?;
+        }
+        Ok(lhs)
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        lhs: Expr,
+        operand: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let (_, pos) = self.advance();
+        let rhs = operand(self)?;
+        Ok(Expr {
+            kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            pos,
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let (tok, pos) = self.advance();
+        let kind = match tok {
+            Tok::Punct("!") => ExprKind::Not(Box::new(self.unary()?)),
+            Tok::Punct("-") => ExprKind::Neg(Box::new(self.unary()?)),
+            Tok::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            Tok::Number(n) if n <= u8::MAX as u32 => ExprKind::Number(n as u8),
+            Tok::Number(n) => return Err(error(pos, format!("{n} does not fit in a cell"))),
+            Tok::Ident(name) if !KEYWORDS.contains(&name.as_str()) => ExprKind::Var(name),
+            tok => return Err(error(pos, format!("Expected an expression, found {tok}"))),
+        };
+        Ok(Expr { kind, pos })
+    }
+}
+
+pub fn parse(source: &str) -> Result<Vec<Stmt>> {
+    let mut tokenizer = Tokenizer {
+        chars: source.chars().peekable(),
+        pos: Pos { line: 1, col: 1 },
+    };
+    let mut tokens = Vec::new();
+    loop {
+        let (tok, pos) = tokenizer.next_token()?;
+        tokens.push((tok.clone(), pos));
+        if tok == Tok::Eof {
+            break;
+        }
+    }
+
+    let mut parser = Parser { tokens, next: 0 };
+    let mut stmts = Vec::new();
+    while *parser.peek() != Tok::Eof {
+        stmts.push(parser.stmt()?);
+    }
+    Ok(stmts)
+}
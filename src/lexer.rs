@@ -1,5 +1,8 @@
 use log::debug;
 
+use crate::dialect::{Dialect, TokenTable};
+use crate::options::Extension;
+
 #[derive(PartialEq, Eq)]
 pub enum Op {
     Modify(i16),
@@ -8,6 +11,16 @@ pub enum Op {
     Inp(u16),
     LBr,
     RBr,
+    /// `#` of `Extension::DebugDump`
+    DebugDump,
+    /// `(` of `Dialect::Pbrain`
+    ProcStart,
+    /// `)` of `Dialect::Pbrain`
+    ProcEnd,
+    /// `:` of `Dialect::Pbrain`
+    Call,
+    /// `Y` of `Dialect::Brainfork`
+    Fork,
 }
 
 impl std::fmt::Debug for Op {
@@ -19,19 +32,18 @@ impl std::fmt::Debug for Op {
             Op::Inp(x) => write!(f, "(inp {x})"),
             Op::LBr => write!(f, "( [ )"),
             Op::RBr => write!(f, "( ] )"),
+            Op::DebugDump => write!(f, "( # )"),
+            Op::ProcStart => write!(f, "( ( )"),
+            Op::ProcEnd => write!(f, "( ) )"),
+            Op::Call => write!(f, "(call)"),
+            Op::Fork => write!(f, "(fork)"),
         }
     }
 }
 
 impl Op {
     fn is_reducible(&self) -> bool {
-        use Op::*;
-
-        match self {
-            Modify(_) => true,
-            Move(_) => true,
-            _ => false,
-        }
+        matches!(self, Op::Modify(_) | Op::Move(_))
     }
 
     fn is_same_operation(&self, other: &Op) -> bool {
@@ -52,11 +64,7 @@ impl Op {
     }
 
     pub fn is_brace(&self) -> bool {
-        use Op::*;
-        match self {
-            LBr | RBr => true,
-            _ => false,
-        }
+        matches!(self, Op::LBr | Op::RBr)
     }
 
     pub fn from_char(c: char) -> Option<Op> {
@@ -75,10 +83,42 @@ impl Op {
     }
 }
 
-fn squash(mut vec: Vec<Op>, next_token: Op) -> Vec<Op> {
+/// 1-based line and column (in characters) of a command in the source
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: u32,
+    pub col: u32,
+}
+
+impl std::fmt::Display for Pos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+impl std::fmt::Debug for Pos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+/// An operation and the position of the first command it was squashed from
+#[derive(PartialEq, Eq)]
+pub struct Token {
+    pub op: Op,
+    pub pos: Pos,
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.op)
+    }
+}
+
+fn squash(mut vec: Vec<Token>, next_token: Token) -> Vec<Token> {
     use Op::*;
 
-    let Some(token) = vec.last_mut() else {
+    let Some(Token { op: token, .. }) = vec.last_mut() else {
         vec.push(next_token);
         return vec;
     };
@@ -88,28 +128,129 @@ fn squash(mut vec: Vec<Op>, next_token: Op) -> Vec<Op> {
         return vec;
     }
 
-    if !token.is_same_operation(&next_token) {
+    if !token.is_same_operation(&next_token.op) {
         vec.push(next_token);
         return vec;
     }
 
-    let update = next_token.get_val();
+    let update = next_token.op.get_val();
 
-    *token = match token {
-        Move(x) => Move(*x + update),
-        Modify(x) => Modify(*x + update),
-        Outp(x) => Outp(*x + update as u16),
-        Inp(x) => Inp(*x + update as u16),
+    // A run too long for one count continues in a new token
+    let squashed = match token {
+        Move(x) => x.checked_add(update).map(Move),
+        Modify(x) => x.checked_add(update).map(Modify),
+        Outp(x) => x.checked_add(update as u16).map(Outp),
+        Inp(x) => x.checked_add(update as u16).map(Inp),
         _ => unreachable!(),
     };
+    match squashed {
+        Some(squashed) => *token = squashed,
+        None => vec.push(next_token),
+    }
 
     vec
 }
 
-pub fn parse(program: &String) -> Vec<Op> {
-    let unflattened = program
-        .chars()
-        .filter_map(Op::from_char)
+fn positions(program: &str) -> impl Iterator<Item = (Pos, char)> + '_ {
+    let mut pos = Pos { line: 1, col: 0 };
+    program.chars().map(move |c| {
+        pos.col += 1;
+        let current = pos;
+        if c == '\n' {
+            pos.line += 1;
+            pos.col = 0;
+        }
+        (current, c)
+    })
+}
+
+/// Splits `source` at the first `!` into the program and the input embedded
+/// after it, see `Extension::InlineInput`
+pub fn split_inline_input(source: &str) -> (&str, Option<&str>) {
+    match source.split_once('!') {
+        Some((program, input)) => (program, Some(input)),
+        None => (source, None),
+    }
+}
+
+/// Length of the match of `token` at the start of `chars`. A space in
+/// `token` matches one or more whitespace characters.
+fn match_token(chars: &[(Pos, char)], token: &str) -> Option<usize> {
+    let mut len = 0;
+    for expected in token.chars() {
+        if expected == ' ' {
+            let spaces = chars[len..]
+                .iter()
+                .take_while(|(_, c)| c.is_whitespace())
+                .count();
+            if spaces == 0 {
+                return None;
+            }
+            len += spaces;
+        } else {
+            if chars.get(len)?.1 != expected {
+                return None;
+            }
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+/// Replaces the tokens of `table` by the brainfuck commands they stand for,
+/// dropping everything else except `#` if `keep_hash` is set
+fn substitute(program: &str, table: &TokenTable, keep_hash: bool) -> Vec<(Pos, char)> {
+    let chars: Vec<(Pos, char)> = positions(program).collect();
+    let mut commands = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let matched = table
+            .tokens()
+            .find_map(|(token, command)| Some((match_token(&chars[i..], token)?, command)));
+        match matched {
+            Some((len, command)) => {
+                commands.push((chars[i].0, command));
+                i += len;
+            }
+            None => {
+                if keep_hash && chars[i].1 == '#' {
+                    commands.push(chars[i]);
+                }
+                i += 1;
+            }
+        }
+    }
+    commands
+}
+
+pub fn parse(program: &str) -> Vec<Token> {
+    parse_with(program, &Dialect::Brainfuck, &[])
+}
+
+/// Like `parse`, additionally recognizing the commands of `dialect` and
+/// `extensions`. `Extension::InlineInput` is handled by `split_inline_input`
+/// before lexing.
+pub fn parse_with(program: &str, dialect: &Dialect, extensions: &[Extension]) -> Vec<Token> {
+    let debug_dump = extensions.contains(&Extension::DebugDump);
+    let pbrain = *dialect == Dialect::Pbrain;
+    let brainfork = *dialect == Dialect::Brainfork;
+    let commands: Vec<(Pos, char)> = match dialect.token_table() {
+        Some(table) => substitute(program, &table, debug_dump),
+        None => positions(program).collect(),
+    };
+    let unflattened = commands
+        .into_iter()
+        .filter_map(|(pos, c)| {
+            let op = match c {
+                '#' if debug_dump => Some(Op::DebugDump),
+                '(' if pbrain => Some(Op::ProcStart),
+                ')' if pbrain => Some(Op::ProcEnd),
+                ':' if pbrain => Some(Op::Call),
+                'Y' if brainfork => Some(Op::Fork),
+                c => Op::from_char(c),
+            };
+            op.map(|op| Token { op, pos })
+        })
         .fold(Vec::new(), squash);
 
     debug!("Parsed: {:?}", unflattened);
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+
+pub mod asm_gen;
+pub mod cdylib;
+pub mod dialect;
+pub mod error;
+pub mod freestanding;
+pub mod generator;
+pub mod interpreter;
+pub mod lang;
+pub mod lexer;
+pub mod linker;
+#[cfg(feature = "llvm")]
+pub mod llvm_ir_gen;
+pub mod optimizations;
+pub mod optimizer;
+pub mod options;
+pub mod parser;
+pub mod preprocessor;
+pub mod profile;
+pub mod wasm_gen;
+
+pub use dialect::Dialect;
+pub use error::{BrainfcError, Location, Result};
+pub use options::{
+    Backend, CompileOptions, CrateType, ExitCode, Extension, IoMode, OptLevel, WasmAbi,
+};
+
+use lexer::{Op, Token};
+use linker::Linker;
+use optimizer::Optimizer;
+use parser::Program;
+
+pub fn lex(source: &str) -> Vec<Token> {
+    lexer::parse(source)
+}
+
+/// `lex` for another dialect or with opt-in language extensions. Split off
+/// inline input with `lexer::split_inline_input` first.
+pub fn lex_with(source: &str, dialect: &Dialect, extensions: &[Extension]) -> Vec<Token> {
+    lexer::parse_with(source, dialect, extensions)
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<Program> {
+    parser::parse(tokens)
+}
+
+pub fn optimize(program: Program) -> Program {
+    Optimizer::new().optimize(program)
+}
+
+/// Runs the selected backend and returns the path of the file it produced:
+/// an object file for native backends, the module itself for `wasm`. For
+/// shared libraries the C header is written next to the output as well.
+pub fn compile_to_object(program: Program, options: &CompileOptions) -> Result<PathBuf> {
+    options.validate()?;
+    if parser::contains_op(&program, &Op::DebugDump)
+        && (options.freestanding || options.backend == Backend::Wasm)
+    {
+        return Err(BrainfcError::Codegen(
+            "`#` needs libc and is not supported by --freestanding or the wasm backend".to_string(),
+        ));
+    }
+    if parser::uses_procedures(&program)
+        && (options.crate_type == CrateType::Cdylib || options.backend == Backend::Wasm)
+    {
+        return Err(BrainfcError::Codegen(
+            "pbrain procedures are only supported in executables built by the native backends"
+                .to_string(),
+        ));
+    }
+    if parser::contains_op(&program, &Op::Fork)
+        && (options.crate_type == CrateType::Cdylib
+            || options.backend == Backend::Wasm
+            || options.freestanding)
+    {
+        return Err(BrainfcError::Codegen(
+            "`Y` needs libc and is only supported in executables built by the native backends"
+                .to_string(),
+        ));
+    }
+
+    let object_file = match options.backend {
+        #[cfg(feature = "llvm")]
+        Backend::Llvm => {
+            llvm_ir_gen::compile(program, options)?;
+            options.object_file()
+        }
+        Backend::Asm => {
+            asm_gen::compile(program, options)?;
+            options.object_file()
+        }
+        Backend::Wasm => return wasm_gen::compile(program, options),
+    };
+
+    if options.crate_type == CrateType::Cdylib {
+        let header_file = options.output.with_extension("h");
+        std::fs::write(
+            &header_file,
+            cdylib::header(&options.output, options.get_io()),
+        )
+        .map_err(|e| BrainfcError::io(format!("Could not write {header_file:?}"), e))?;
+    }
+
+    Ok(object_file)
+}
+
+/// Links an object file produced by [`compile_to_object`] into an executable.
+pub fn link(object_file: &std::path::Path, options: &CompileOptions) -> Result<PathBuf> {
+    Linker::resolve(options)?.link(object_file, options)
+}
+
+/// Compiles and links `program`, removing the intermediate object file. The
+/// `wasm` backend has no link step, so the module is returned as is.
+pub fn compile_to_executable(program: Program, options: &CompileOptions) -> Result<PathBuf> {
+    let object_file = compile_to_object(program, options)?;
+    if options.backend == Backend::Wasm {
+        return Ok(object_file);
+    }
+
+    let executable = link(&object_file, options)?;
+    std::fs::remove_file(&object_file)
+        .map_err(|e| BrainfcError::io(format!("Could not remove {object_file:?}"), e))?;
+    Ok(executable)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{lexer, parser};
+
+    macro_rules! build_ir_test {
+        ($name:ident) => {
+            #[test]
+            pub fn $name() {
+                let input =
+                    std::fs::read_to_string(format!("tests/{}.input", stringify!($name))).unwrap();
+                let expected_output =
+                    std::fs::read_to_string(format!("tests/{}.output", stringify!($name))).unwrap();
+                let parsed = parser::parse(lexer::parse(&input)).unwrap();
+                assert_eq!(format!("{:?}\n", parsed), expected_output);
+            }
+        };
+    }
+
+    build_ir_test!(comments);
+    build_ir_test!(empty_file);
+    build_ir_test!(mandelbrot);
+    build_ir_test!(simple_1);
+
+    #[test]
+    fn unmatched_brackets_are_errors() {
+        for source in ["]", "+]", "[", "[[]", "][", "[]]["] {
+            assert!(parser::parse(lexer::parse(source)).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn long_runs_are_split() {
+        for command in ["+", "-", ">", "<"] {
+            let tokens = lexer::parse(&command.repeat(70000));
+            let total: i64 = tokens.iter().map(|t| (t.op.get_val() as i64).abs()).sum();
+            assert_eq!(total, 70000, "{command}");
+        }
+    }
+}
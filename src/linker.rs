@@ -0,0 +1,200 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{BrainfcError, Result};
+use crate::options::{CompileOptions, CrateType};
+
+/// Candidates tried, in order, when no linker is given explicitly
+const DEFAULT_LINKERS: &[&str] = &["cc", "clang", "gcc", "ld.lld"];
+
+// Needed only when invoking a bare `ld`, compiler drivers know these already
+const CRT_DIRS: &[&str] = &["/usr/lib/x86_64-linux-gnu", "/usr/lib64", "/usr/lib"];
+const DYNAMIC_LINKER: &str = "/lib64/ld-linux-x86-64.so.2";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkerFlavor {
+    /// A compiler driver such as `cc`, `gcc` or `clang`
+    Cc,
+    /// A bare linker such as `ld`, `ld.lld` or `ld.gold`
+    Ld,
+}
+
+#[derive(Clone, Debug)]
+pub struct Linker {
+    program: PathBuf,
+    flavor: LinkerFlavor,
+}
+
+impl fmt::Display for Linker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.display())
+    }
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn find_crt_dir() -> Option<&'static Path> {
+    CRT_DIRS
+        .iter()
+        .map(Path::new)
+        .find(|dir| dir.join("crt1.o").is_file())
+}
+
+impl Linker {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        let program = program.into();
+        let name = program
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let flavor = if name == "ld" || name == "lld" || name.starts_with("ld.") {
+            LinkerFlavor::Ld
+        } else {
+            LinkerFlavor::Cc
+        };
+
+        Linker { program, flavor }
+    }
+
+    pub fn detect() -> Option<Self> {
+        DEFAULT_LINKERS
+            .iter()
+            .find_map(|name| find_in_path(name))
+            .map(Linker::new)
+    }
+
+    /// The linker requested in `options`, or the first one found on `PATH`
+    pub fn resolve(options: &CompileOptions) -> Result<Self> {
+        if let Some(linker) = &options.linker {
+            return Ok(Linker::new(linker));
+        }
+
+        Self::detect().ok_or_else(|| {
+            BrainfcError::Link(format!(
+                "No linker found (tried {}), use --linker to choose one",
+                DEFAULT_LINKERS.join(", ")
+            ))
+        })
+    }
+
+    pub fn flavor(&self) -> LinkerFlavor {
+        self.flavor
+    }
+
+    fn command(&self, object_file: &Path, options: &CompileOptions) -> Result<Command> {
+        let mut command = Command::new(&self.program);
+
+        match self.flavor {
+            _ if options.crate_type == CrateType::Cdylib => {
+                command.arg("-shared").arg(object_file);
+            }
+            LinkerFlavor::Cc if options.freestanding => {
+                command.args(["-nostdlib", "-static"]).arg(object_file);
+            }
+            LinkerFlavor::Cc => {
+                if options.static_linking {
+                    command.arg("-static");
+                }
+                command.arg(object_file);
+            }
+            LinkerFlavor::Ld if options.freestanding => {
+                command.arg("-static").arg(object_file);
+            }
+            LinkerFlavor::Ld => {
+                let crt_dir = find_crt_dir().ok_or_else(|| {
+                    BrainfcError::Link(format!(
+                        "{self} needs the C runtime objects, but crt1.o was not found in {}",
+                        CRT_DIRS.join(", ")
+                    ))
+                })?;
+
+                if options.static_linking {
+                    command.arg("-static");
+                } else {
+                    command.args(["-dynamic-linker", DYNAMIC_LINKER]);
+                }
+                command
+                    .arg(crt_dir.join("crt1.o"))
+                    .arg(crt_dir.join("crti.o"))
+                    .arg(object_file)
+                    .arg(format!("-L{}", crt_dir.display()))
+                    .arg("-lc")
+                    .arg(crt_dir.join("crtn.o"));
+            }
+        }
+
+        command
+            .args(&options.link_args)
+            .arg("-o")
+            .arg(options.artifact());
+        Ok(command)
+    }
+
+    pub fn link(&self, object_file: &Path, options: &CompileOptions) -> Result<PathBuf> {
+        let mut command = self.command(object_file, options)?;
+        log::debug!("linking: {:?}", command);
+
+        let output = command
+            .output()
+            .map_err(|e| BrainfcError::Link(format!("Could not run {self}: {e}")))?;
+
+        if !output.status.success() {
+            return Err(BrainfcError::Link(format!(
+                "{self} exited with {}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+
+        Ok(options.artifact())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Linker, LinkerFlavor};
+    use crate::{BrainfcError, CompileOptions};
+
+    #[test]
+    fn flavor_follows_the_name() {
+        for name in ["ld", "lld", "ld.lld", "/usr/bin/ld.gold"] {
+            assert_eq!(Linker::new(name).flavor(), LinkerFlavor::Ld, "{name}");
+        }
+        for name in [
+            "cc",
+            "gcc",
+            "clang-15",
+            "/usr/bin/x86_64-linux-gnu-gcc",
+            "ldd-wrapper",
+        ] {
+            assert_eq!(Linker::new(name).flavor(), LinkerFlavor::Cc, "{name}");
+        }
+    }
+
+    #[test]
+    fn explicit_linker_wins() {
+        let options = CompileOptions::new().linker("/opt/bin/ld.mold");
+        let linker = Linker::resolve(&options).unwrap();
+        assert_eq!(linker.to_string(), "/opt/bin/ld.mold");
+        assert_eq!(linker.flavor(), LinkerFlavor::Ld);
+    }
+
+    #[test]
+    fn failures_are_link_errors() {
+        let options = CompileOptions::new().output(std::env::temp_dir().join("brainfc-link-test"));
+        let object = std::path::Path::new("missing.o");
+
+        let err = Linker::new("brainfc-no-such-linker").link(object, &options);
+        assert!(matches!(err, Err(BrainfcError::Link(msg)) if msg.starts_with("Could not run")));
+
+        let err = Linker::new("false").link(object, &options);
+        assert!(matches!(err, Err(BrainfcError::Link(msg)) if msg.contains("exited with")));
+    }
+}
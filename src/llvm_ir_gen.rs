@@ -1,3 +1,4 @@
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
@@ -5,27 +6,43 @@ use inkwell::targets::{
     CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
 };
 
-use inkwell::values::{IntValue, PointerValue};
+use inkwell::types::BasicMetadataTypeEnum;
+use inkwell::values::{BasicMetadataValueEnum, IntValue, PointerValue};
 use inkwell::AddressSpace;
 use inkwell::OptimizationLevel;
 
+mod debug_info;
+mod dump_tape;
+mod fork;
+mod io;
 mod loops;
 mod modify_block;
 mod move_block;
+mod procedures;
+mod profile;
 mod read_char;
 mod reset;
 mod write_char;
 
+use debug_info::DebugInfo;
+use dump_tape::DumpTape;
+use fork::Fork;
+use io::Io;
 use loops::Loop;
 use modify_block::ModifyBlock;
 use move_block::MoveBlock;
+use procedures::Procedures;
+use profile::Profile;
 use read_char::ReadChar;
 use reset::Reset;
 use write_char::WriteChar;
 
-use crate::app::Args;
-use crate::lexer::Op;
-use crate::parser::{Block, Program};
+use crate::cdylib::TAPE_SIZE;
+use crate::error::{BrainfcError, Result};
+use crate::freestanding::RUNTIME_ASM;
+use crate::lexer::{Op, Pos, Token};
+use crate::options::{CompileOptions, CrateType, ExitCode, IoMode, OptLevel};
+use crate::parser::{Block, Program, Span};
 
 struct LLVMBuilder<'ctxt, 'a> {
     context: &'ctxt Context,
@@ -38,11 +55,26 @@ struct LLVMBuilder<'ctxt, 'a> {
     read_char: ReadChar<'ctxt, 'a>,
     reset: Reset<'ctxt, 'a>,
     loops: Loop<'ctxt, 'a>,
+    dump_tape: DumpTape<'ctxt, 'a>,
+    debug_info: Option<DebugInfo<'ctxt>>,
+    profile: Option<Profile<'ctxt, 'a>>,
+    procedures: Option<Procedures<'ctxt, 'a>>,
+    fork: Option<Fork<'ctxt, 'a>>,
 
     tape: PointerValue<'ctxt>,
     tape_pos: PointerValue<'ctxt>,
+    io_state: Option<PointerValue<'ctxt>>,
 
-    args: Args,
+    options: &'a CompileOptions,
+}
+
+/// State the program runs on, set up by the entry function
+struct Entry<'ctxt> {
+    /// Pointer to the first cell
+    tape: PointerValue<'ctxt>,
+    tape_pos: PointerValue<'ctxt>,
+    io: Io<'ctxt>,
+    io_state: Option<PointerValue<'ctxt>>,
 }
 
 impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
@@ -50,16 +82,47 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
         context: &'ctxt Context,
         module: &'a Module<'ctxt>,
         builder: &'a Builder<'ctxt>,
-        args: Args,
+        options: &'a CompileOptions,
+        entry: Entry<'ctxt>,
+        program: &Program,
     ) -> Self {
-        let (tape, tape_pos) = Self::create_global_variables(context, module);
-
-        let move_block = MoveBlock::new(context, module, builder, tape, tape_pos);
-        let modify_block = ModifyBlock::new(context, module, builder, tape, tape_pos);
-        let write_char = WriteChar::new(context, module, builder, tape, tape_pos);
-        let reset = Reset::new(context, module, builder, tape, tape_pos);
-        let read_char = ReadChar::new(context, module, builder, tape, tape_pos);
-        let loops = Loop::new(context, module, builder, tape, tape_pos);
+        let Entry {
+            tape,
+            tape_pos,
+            io,
+            io_state,
+        } = entry;
+
+        let move_block = MoveBlock::new(context, builder, tape_pos);
+        let modify_block = ModifyBlock::new(context, builder, tape, tape_pos);
+        let write_char = WriteChar::new(context, builder, tape, tape_pos, io.clone());
+        let reset = Reset::new(context, builder, tape, tape_pos);
+        let read_char = ReadChar::new(context, builder, tape, tape_pos, io);
+        let loops = Loop::new(context, builder, tape, tape_pos);
+        let dump_tape = DumpTape::new(context, module, builder, tape, tape_pos);
+        // Built up front, before any debug location is attached
+        if options.dump_tape_at_exit || crate::parser::contains_op(program, &Op::DebugDump) {
+            dump_tape.get_or_create_dump_fn();
+        }
+        let procedures = crate::parser::uses_procedures(program)
+            .then(|| Procedures::new(context, module, builder, tape, tape_pos));
+        let fork = crate::parser::contains_op(program, &Op::Fork)
+            .then(|| Fork::new(context, module, builder, tape, tape_pos));
+
+        let debug_info = options.debug_source.as_ref().map(|source| {
+            let debug_info = DebugInfo::new(context, module, source);
+            let current_fn = builder
+                .get_insert_block()
+                .and_then(|block| block.get_parent())
+                .unwrap();
+            let name = current_fn.get_name().to_string_lossy().into_owned();
+            debug_info.enter_function(current_fn, &name);
+            debug_info
+        });
+
+        let profile = options
+            .profile
+            .then(|| Profile::new(context, module, builder, crate::profile::sites(program)));
 
         LLVMBuilder {
             move_block,
@@ -68,40 +131,26 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
             reset,
             read_char,
             loops,
+            dump_tape,
+            debug_info,
+            profile,
+            procedures,
+            fork,
 
             context,
             module,
             builder,
-            args,
+            options,
             tape,
             tape_pos,
+            io_state,
         }
     }
 
-    fn create_global_variables<'m>(
-        context: &'m Context,
-        module: &Module<'m>,
-    ) -> (PointerValue<'m>, PointerValue<'m>) {
-        let i32_type = context.i32_type();
-        let i32_arr_type = i32_type.array_type(30_000);
-        let addr_space = Some(AddressSpace::default());
-
-        let tape = module.add_global(i32_arr_type, addr_space, "tape");
-        tape.set_initializer(&i32_type.array_type(30_000).const_zero());
-
-        let tape_pos = module.add_global(i32_type, addr_space, "tape_block");
-        tape_pos.set_initializer(&i32_type.const_zero());
-
-        (tape.as_pointer_value(), tape_pos.as_pointer_value())
-    }
-
-    fn compare_with_zero(&self, val: IntValue<'ctxt>) -> IntValue<'ctxt> {
-        self.builder.build_int_compare(
-            inkwell::IntPredicate::NE,
-            val,
-            self.context.i8_type().const_zero(),
-            "compare value at pointer to zero",
-        )
+    fn create_tape_pos(context: &'ctxt Context, builder: &Builder<'ctxt>) -> PointerValue<'ctxt> {
+        let tape_pos = builder.build_alloca(context.i32_type(), "tape_pos");
+        builder.build_store(tape_pos, context.i32_type().const_zero());
+        tape_pos
     }
 
     fn build_multiply(&self, ops: Vec<(i32, i32)>) {
@@ -111,14 +160,8 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
             .into_int_value();
 
         let ptr_to_base_value = unsafe {
-            self.builder.build_gep(
-                self.tape,
-                &[
-                    self.context.i32_type().const_int(0, false),
-                    current_tape_pos,
-                ],
-                "ptr_to_value",
-            )
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
         };
 
         let base_value = self
@@ -134,14 +177,8 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
                 "modification_pos",
             );
             let ptr_to_value = unsafe {
-                self.builder.build_gep(
-                    self.tape,
-                    &[
-                        self.context.i32_type().const_int(0, false),
-                        modification_pos,
-                    ],
-                    "ptr_to_value",
-                )
+                self.builder
+                    .build_gep(self.tape, &[modification_pos], "ptr_to_value")
             };
             let value = self
                 .builder
@@ -151,9 +188,7 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
             // Calculate the new value
             let multipled = self.builder.build_int_mul(
                 base_value,
-                self.context
-                    .i32_type()
-                    .const_int(multiply_val as u64, false),
+                self.context.i8_type().const_int(multiply_val as u64, false),
                 "multipled",
             );
             let new_value = self.builder.build_int_add(value, multipled, "new_value");
@@ -162,55 +197,247 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
             self.builder.build_store(ptr_to_value, new_value);
         }
 
-        self.builder.build_store(
-            ptr_to_base_value,
-            self.context.i32_type().const_int(0, false),
+        self.builder
+            .build_store(ptr_to_base_value, self.context.i8_type().const_zero());
+    }
+
+    /// `Io::stdio`, or `Io::embedded` if the program has inline input. Leaves
+    /// the builder at the end of `block`.
+    fn stdio(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        block: BasicBlock<'ctxt>,
+        embedded_input: Option<&[u8]>,
+    ) -> Io<'ctxt> {
+        let io = match embedded_input {
+            Some(input) => Io::embedded(context, module, builder, input),
+            None => Io::stdio(context, module),
+        };
+        builder.position_at_end(block);
+        io
+    }
+
+    /// `int main()`. If `global_tape_pos` is set, the tape position is a
+    /// global instead of a local, as pbrain procedures need.
+    fn create_main_fn(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        embedded_input: Option<&[u8]>,
+        global_tape_pos: bool,
+    ) -> Entry<'ctxt> {
+        let tape_type = context.i8_type().array_type(TAPE_SIZE as u32);
+        let tape = module.add_global(tape_type, Some(AddressSpace::default()), "tape");
+        tape.set_initializer(&tape_type.const_zero());
+
+        let main_fn_type = context.i32_type().fn_type(&[], false);
+        let main_fn = module.add_function("main", main_fn_type, Some(Linkage::External));
+        let main_entry = context.append_basic_block(main_fn, "entry");
+
+        let io = Self::stdio(context, module, builder, main_entry, embedded_input);
+
+        let tape = builder.build_pointer_cast(
+            tape.as_pointer_value(),
+            context.i8_type().ptr_type(AddressSpace::default()),
+            "tape",
         );
+
+        let tape_pos = if global_tape_pos {
+            let tape_pos = module.add_global(context.i32_type(), None, "bf_tape_pos");
+            tape_pos.set_linkage(Linkage::Internal);
+            tape_pos.set_initializer(&context.i32_type().const_zero());
+            tape_pos.as_pointer_value()
+        } else {
+            Self::create_tape_pos(context, builder)
+        };
+
+        Entry {
+            tape,
+            tape_pos,
+            io,
+            io_state: None,
+        }
+    }
+
+    /// `int bf_run_tape(uint8_t *tape, ...)`, followed by the parameters
+    /// `io_mode` needs, see `cdylib::header`
+    fn create_run_tape_fn(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        io_mode: IoMode,
+        embedded_input: Option<&[u8]>,
+    ) -> Entry<'ctxt> {
+        let i8_ptr = context.i8_type().ptr_type(AddressSpace::default());
+        let i64_type = context.i64_type();
+        let mut param_types: Vec<BasicMetadataTypeEnum> = vec![i8_ptr.into()];
+        match io_mode {
+            IoMode::Stdio => {}
+            IoMode::Buffer => param_types.extend::<[BasicMetadataTypeEnum; 5]>([
+                i8_ptr.into(),
+                i64_type.into(),
+                i8_ptr.into(),
+                i64_type.into(),
+                i64_type.ptr_type(AddressSpace::default()).into(),
+            ]),
+            IoMode::Callback => param_types.extend::<[BasicMetadataTypeEnum; 3]>([
+                Io::read_callback_type(context)
+                    .ptr_type(AddressSpace::default())
+                    .into(),
+                Io::write_callback_type(context)
+                    .ptr_type(AddressSpace::default())
+                    .into(),
+                i8_ptr.into(),
+            ]),
+        }
+        let run_fn_type = context.i32_type().fn_type(&param_types, false);
+        let run_fn = module.add_function("bf_run_tape", run_fn_type, Some(Linkage::External));
+        let run_entry = context.append_basic_block(run_fn, "entry");
+        let param = |i: u32| run_fn.get_nth_param(i).unwrap().into_pointer_value();
+
+        builder.position_at_end(run_entry);
+        let (io, io_state) = match io_mode {
+            IoMode::Stdio => {
+                let io = Self::stdio(context, module, builder, run_entry, embedded_input);
+                if embedded_input.is_some() {
+                    Io::reset_embedded_input(context, module, builder);
+                }
+                (io, None)
+            }
+            IoMode::Buffer => {
+                let io_state = builder.build_alloca(Io::buffer_state_type(context), "io_state");
+                let io = Io::buffer(context, module, builder, io_state);
+
+                builder.position_at_end(run_entry);
+                Io::init_buffer_state(context, builder, io_state, run_fn, 1);
+                (io, Some(io_state))
+            }
+            IoMode::Callback => (Io::callback(param(1), param(2), param(3)), None),
+        };
+
+        Entry {
+            tape: param(0),
+            tape_pos: Self::create_tape_pos(context, builder),
+            io,
+            io_state,
+        }
     }
 
-    fn create_main_fn(&self) {
-        let main_fn_type = self.context.i32_type().fn_type(&[], false);
-        let main_fn = self
+    /// `int bf_run(...)` with the parameters of `bf_run_tape` except the
+    /// tape, calls `bf_run_tape` with a zeroed tape on the stack
+    fn create_run_fn(&self) {
+        let run_tape_fn = self.module.get_function("bf_run_tape").unwrap();
+        let param_types: Vec<_> = run_tape_fn
+            .get_type()
+            .get_param_types()
+            .into_iter()
+            .skip(1)
+            .map(|t| t.into())
+            .collect();
+        let run_fn_type = self.context.i32_type().fn_type(&param_types, false);
+        let run_fn = self
             .module
-            .add_function("main", main_fn_type, Some(Linkage::External));
-        let main_entry = self.context.append_basic_block(main_fn, "entry");
+            .add_function("bf_run", run_fn_type, Some(Linkage::External));
+        let run_entry = self.context.append_basic_block(run_fn, "entry");
 
-        self.builder.position_at_end(main_entry);
+        self.builder.position_at_end(run_entry);
+        let tape_size = self.context.i64_type().const_int(TAPE_SIZE as u64, false);
+        let tape = self
+            .builder
+            .build_array_alloca(self.context.i8_type(), tape_size, "tape");
+        self.builder
+            .build_memset(tape, 1, self.context.i8_type().const_zero(), tape_size)
+            .unwrap();
+
+        let mut args: Vec<BasicMetadataValueEnum> = vec![tape.into()];
+        args.extend(run_fn.get_param_iter().map(BasicMetadataValueEnum::from));
+        let result = self
+            .builder
+            .build_call(run_tape_fn, &args, "result")
+            .try_as_basic_value()
+            .unwrap_left();
+        self.builder.build_return(Some(&result));
     }
 
-    fn load_libc(&mut self) {
-        let calloc_fn_type = self
-            .context
-            .i8_type()
-            .ptr_type(AddressSpace::default())
-            .fn_type(
-                &[
-                    self.context.i64_type().into(),
-                    self.context.i64_type().into(),
-                ],
-                false,
-            );
-        self.module
-            .add_function("calloc", calloc_fn_type, Some(Linkage::External));
-        let getchar_fn_type = self.context.i32_type().fn_type(&[], false);
-        self.module
-            .add_function("getchar", getchar_fn_type, Some(Linkage::External));
-
-        let putchar_fn_type = self
-            .context
-            .i32_type()
-            .fn_type(&[self.context.i32_type().into()], false);
-        self.module
-            .add_function("putchar", putchar_fn_type, Some(Linkage::External));
+    /// The current cell, zero-extended to `i32`
+    fn build_load_cell(&self) -> IntValue<'ctxt> {
+        let current_tape_pos = self
+            .builder
+            .build_load(self.tape_pos, "tape_pos")
+            .into_int_value();
+        let ptr_to_value = unsafe {
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
+        };
+        let value = self
+            .builder
+            .build_load(ptr_to_value, "value")
+            .into_int_value();
+        self.builder
+            .build_int_z_extend(value, self.context.i32_type(), "exit_code")
+    }
+
+    /// Sets the module assembly to the runtimes `options` need. LLVM keeps a
+    /// single module assembly string, so they are joined and set at once.
+    fn load_runtimes(module: &Module<'ctxt>, options: &CompileOptions) {
+        let mut runtimes = String::new();
+        if options.freestanding {
+            runtimes.push_str(RUNTIME_ASM);
+        }
+        if options.profile {
+            runtimes.push_str(crate::profile::RUNTIME_ASM);
+        }
+        if !runtimes.is_empty() {
+            module.set_inline_assembly(&runtimes);
+        }
+    }
+
+    fn set_location(&self, pos: Pos) {
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.set_location(self.builder, pos);
+        }
     }
 
     fn finalize(&self) {
-        self.builder
-            .build_return(Some(&self.context.i32_type().const_int(0, false)));
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.clear_location(self.builder);
+        }
+
+        match self.io_state {
+            Some(io_state) => {
+                let truncated = Io::load_truncated(self.builder, io_state);
+                self.builder.build_return(Some(&truncated));
+            }
+            None => {
+                if let Some(fork) = &self.fork {
+                    fork.build_wait_children();
+                }
+                if let Some(profile) = &self.profile {
+                    profile.build_report();
+                }
+                if self.options.dump_tape_at_exit {
+                    self.dump_tape.build();
+                }
+                let exit_code = match self.options.exit_code {
+                    ExitCode::Zero => self.context.i32_type().const_int(0, false),
+                    ExitCode::Cell => self.build_load_cell(),
+                };
+                self.builder.build_return(Some(&exit_code));
+            }
+        }
+
+        if self.options.crate_type == CrateType::Cdylib {
+            self.create_run_fn();
+        }
     }
 
-    fn create_binary(&self) {
-        if self.args.show_llvm_ir {
+    fn create_binary(&self) -> Result<()> {
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.finalize();
+        }
+
+        if self.options.show_ir {
             self.module.print_to_stderr();
         }
 
@@ -220,30 +447,32 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
         let cpu = TargetMachine::get_host_cpu_name().to_string();
         let features = TargetMachine::get_host_cpu_features().to_string();
 
-        let target = Target::from_triple(&target_triple).unwrap();
+        let target =
+            Target::from_triple(&target_triple).map_err(|e| BrainfcError::Target(e.to_string()))?;
 
         let target_machine = target
             .create_target_machine(
                 &target_triple,
                 &cpu,
                 &features,
-                OptimizationLevel::Default,
-                RelocMode::Default,
+                match self.options.opt_level {
+                    OptLevel::None => OptimizationLevel::None,
+                    OptLevel::Less => OptimizationLevel::Less,
+                    OptLevel::Default => OptimizationLevel::Default,
+                    OptLevel::Aggressive => OptimizationLevel::Aggressive,
+                },
+                // `cc` links position independent executables by default,
+                // which may not refer to globals by absolute address
+                RelocMode::PIC,
                 CodeModel::Default,
             )
-            .ok_or_else(|| "Unable to create target machine!".to_string())
-            .unwrap();
+            .ok_or_else(|| BrainfcError::Target("Unable to create target machine!".to_string()))?;
 
-        let output = self
-            .args
-            .output
-            .as_ref()
-            .unwrap_or(&std::path::PathBuf::new().with_file_name("out"))
-            .with_extension("o");
+        let output = self.options.object_file();
 
         target_machine
             .write_to_file(self.module, FileType::Object, output.as_path())
-            .unwrap();
+            .map_err(|e| BrainfcError::Codegen(e.to_string()))
     }
 
     fn compile(&self, program: &Program) {
@@ -252,9 +481,10 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
         }
     }
 
-    fn compile_instructions(&self, instrs: &Vec<Op>) {
+    fn compile_instructions(&self, instrs: &Vec<Token>) {
         for i in instrs {
-            self.compile_instruction(i)
+            self.set_location(i.pos);
+            self.compile_instruction(&i.op)
         }
     }
 
@@ -272,42 +502,102 @@ impl<'ctxt, 'a> LLVMBuilder<'ctxt, 'a> {
                     self.write_char.build()
                 }
             }
+            Op::DebugDump => self.dump_tape.build(),
+            Op::Call => self.procedures.as_ref().unwrap().build_call(),
+            Op::Fork => self.fork.as_ref().unwrap().build(),
             _ => unreachable!(),
         }
     }
 
     fn compile_block(&self, block: &Block) {
         use Block::*;
+        if let Some(profile) = self.profile.as_ref().filter(|_| !block.is_loop()) {
+            profile.build_count();
+        }
         match &block {
             Simple(ref instrs) => self.compile_instructions(instrs),
-            Loop(ref program) => self.compile_loop(program),
-            Reset { .. } => self.reset.build(),
-            Multiply { ops } => self.build_multiply(ops.clone()),
+            Loop(ref program, span) => self.compile_loop(program, *span),
+            Procedure(ref program, span) => self.compile_procedure(program, *span),
+            Reset { span, .. } => {
+                self.set_location(span.start);
+                self.reset.build()
+            }
+            Multiply { ops, span } => {
+                self.set_location(span.start);
+                self.build_multiply(ops.clone())
+            }
             _ => {}
         }
     }
 
-    fn compile_loop(&self, program: &Program) {
+    fn compile_loop(&self, program: &Program, span: Span) {
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.enter_loop(span.start);
+        }
+        self.set_location(span.start);
         let (loop_block, cont_block) = self.loops.build_loop_start();
+        if let Some(profile) = &self.profile {
+            profile.build_count();
+        }
 
         self.compile(program);
 
+        self.set_location(span.end);
         self.loops.build_loop_end(loop_block, cont_block);
+        if let Some(debug_info) = &self.debug_info {
+            debug_info.exit_loop();
+        }
+    }
+
+    fn compile_procedure(&self, program: &Program, span: Span) {
+        let procedures = self.procedures.as_ref().unwrap();
+        let (procedure, caller_block) = procedures.build_start();
+        let caller_scopes = self.debug_info.as_ref().map(|debug_info| {
+            debug_info.clear_location(self.builder);
+            let name = procedure.get_name().to_string_lossy().into_owned();
+            debug_info.enter_function(procedure, &name)
+        });
+
+        self.compile(program);
+
+        self.set_location(span.end);
+        procedures.build_end(caller_block);
+        if let (Some(debug_info), Some(scopes)) = (&self.debug_info, caller_scopes) {
+            debug_info.exit_function(scopes);
+        }
+        self.set_location(span.start);
+        procedures.build_define(procedure);
     }
 }
 
-pub fn compile(program: Program, args: Args) {
+pub fn compile(program: Program, options: &CompileOptions) -> Result<()> {
     let context = Context::create();
     let module = context.create_module("brainf");
     let builder = context.create_builder();
 
-    let mut llvm_builder = LLVMBuilder::new(&context, &module, &builder, args);
-
-    llvm_builder.load_libc();
-    llvm_builder.create_main_fn();
+    LLVMBuilder::load_runtimes(&module, options);
+    let embedded_input = options.embedded_input.as_deref();
+    let entry = match options.crate_type {
+        CrateType::Bin => LLVMBuilder::create_main_fn(
+            &context,
+            &module,
+            &builder,
+            embedded_input,
+            crate::parser::uses_procedures(&program),
+        ),
+        CrateType::Cdylib => LLVMBuilder::create_run_tape_fn(
+            &context,
+            &module,
+            &builder,
+            options.get_io(),
+            embedded_input,
+        ),
+    };
+
+    let llvm_builder = LLVMBuilder::new(&context, &module, &builder, options, entry, &program);
 
     llvm_builder.compile(&program);
 
     llvm_builder.finalize();
-    llvm_builder.create_binary();
+    llvm_builder.create_binary()
 }
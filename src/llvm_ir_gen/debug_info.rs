@@ -0,0 +1,134 @@
+use std::cell::RefCell;
+use std::path::Path;
+
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DIScope, DWARFEmissionKind,
+    DWARFSourceLanguage, DebugInfoBuilder,
+};
+use inkwell::module::{FlagBehavior, Module};
+use inkwell::values::FunctionValue;
+
+use crate::lexer::Pos;
+
+/// DWARF line info mapping generated code to positions in the source file.
+/// Functions get a subprogram and every loop a lexical block.
+pub struct DebugInfo<'ctxt> {
+    context: &'ctxt Context,
+    dibuilder: DebugInfoBuilder<'ctxt>,
+    compile_unit: DICompileUnit<'ctxt>,
+    scopes: RefCell<Vec<DIScope<'ctxt>>>,
+}
+
+impl<'ctxt> DebugInfo<'ctxt> {
+    pub fn new(context: &'ctxt Context, module: &Module<'ctxt>, source: &Path) -> Self {
+        let filename = source
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let directory = source
+            .parent()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        module.add_basic_value_flag(
+            "Debug Info Version",
+            FlagBehavior::Warning,
+            context.i32_type().const_int(3, false),
+        );
+
+        let (dibuilder, compile_unit) = module.create_debug_info_builder(
+            true,
+            DWARFSourceLanguage::C,
+            &filename,
+            &directory,
+            concat!("brainfc ", env!("CARGO_PKG_VERSION")),
+            false,
+            "",
+            0,
+            "",
+            DWARFEmissionKind::Full,
+            0,
+            false,
+            false,
+            "",
+            "",
+        );
+
+        DebugInfo {
+            context,
+            dibuilder,
+            compile_unit,
+            scopes: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Attaches a subprogram to `function` and makes it the current scope.
+    /// Returns the scopes of the previous function for `exit_function`.
+    pub fn enter_function(
+        &self,
+        function: FunctionValue<'ctxt>,
+        name: &str,
+    ) -> Vec<DIScope<'ctxt>> {
+        let file = self.compile_unit.get_file();
+        let subroutine_type =
+            self.dibuilder
+                .create_subroutine_type(file, None, &[], DIFlags::PUBLIC);
+        let subprogram = self.dibuilder.create_function(
+            self.compile_unit.as_debug_info_scope(),
+            name,
+            None,
+            file,
+            1,
+            subroutine_type,
+            false,
+            true,
+            1,
+            DIFlags::PUBLIC,
+            false,
+        );
+        function.set_subprogram(subprogram);
+
+        self.scopes.replace(vec![subprogram.as_debug_info_scope()])
+    }
+
+    pub fn exit_function(&self, scopes: Vec<DIScope<'ctxt>>) {
+        self.scopes.replace(scopes);
+    }
+
+    pub fn enter_loop(&self, start: Pos) {
+        let mut scopes = self.scopes.borrow_mut();
+        let parent = *scopes.last().unwrap();
+        let block = self.dibuilder.create_lexical_block(
+            parent,
+            self.compile_unit.get_file(),
+            start.line,
+            start.col,
+        );
+        scopes.push(block.as_debug_info_scope());
+    }
+
+    pub fn exit_loop(&self) {
+        self.scopes.borrow_mut().pop();
+    }
+
+    /// Attributes the instructions built from now on to `pos`
+    pub fn set_location(&self, builder: &Builder<'ctxt>, pos: Pos) {
+        let scope = *self.scopes.borrow().last().unwrap();
+        let location =
+            self.dibuilder
+                .create_debug_location(self.context, pos.line, pos.col, scope, None);
+        builder.set_current_debug_location(location);
+    }
+
+    /// Stops attaching locations, needed before building functions without
+    /// a subprogram
+    pub fn clear_location(&self, builder: &Builder<'ctxt>) {
+        builder.unset_current_debug_location();
+    }
+
+    pub fn finalize(&self) {
+        self.dibuilder.finalize();
+    }
+}
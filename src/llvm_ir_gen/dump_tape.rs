@@ -0,0 +1,167 @@
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{BasicMetadataValueEnum, FunctionValue, PointerValue};
+use inkwell::AddressSpace;
+use inkwell::IntPredicate;
+
+use crate::cdylib::TAPE_SIZE;
+
+const STDERR: u64 = 2;
+// Cells printed on either side of the tape pointer
+const WINDOW: u64 = 8;
+
+pub struct DumpTape<'ctxt, 'a> {
+    context: &'ctxt Context,
+    module: &'a Module<'ctxt>,
+    builder: &'a Builder<'ctxt>,
+
+    tape: PointerValue<'ctxt>,
+    tape_pos: PointerValue<'ctxt>,
+}
+
+impl<'ctxt, 'a> DumpTape<'ctxt, 'a> {
+    pub fn new(
+        context: &'ctxt Context,
+        module: &'a Module<'ctxt>,
+        builder: &'a Builder<'ctxt>,
+        tape: PointerValue<'ctxt>,
+        tape_pos: PointerValue<'ctxt>,
+    ) -> Self {
+        Self {
+            context,
+            module,
+            builder,
+            tape,
+            tape_pos,
+        }
+    }
+
+    fn get_dprintf(&self) -> FunctionValue<'ctxt> {
+        self.module.get_function("dprintf").unwrap_or_else(|| {
+            let i8_ptr = self.context.i8_type().ptr_type(AddressSpace::default());
+            let dprintf_fn_type = self
+                .context
+                .i32_type()
+                .fn_type(&[self.context.i32_type().into(), i8_ptr.into()], true);
+            self.module
+                .add_function("dprintf", dprintf_fn_type, Some(Linkage::External))
+        })
+    }
+
+    fn build_print(&self, format: &str, args: &[BasicMetadataValueEnum<'ctxt>]) {
+        let format = self.builder.build_global_string_ptr(format, "format");
+        let mut all_args: Vec<BasicMetadataValueEnum> = vec![
+            self.context.i32_type().const_int(STDERR, false).into(),
+            format.as_pointer_value().into(),
+        ];
+        all_args.extend_from_slice(args);
+        self.builder.build_call(self.get_dprintf(), &all_args, "_");
+    }
+
+    /// `void bf_dump_tape(uint8_t *tape, int pos)`: prints `pos` and up to
+    /// `WINDOW` cells on either side of it to stderr. Created once and
+    /// shared by all calls; the builder is moved back to where it was.
+    pub fn get_or_create_dump_fn(&self) -> FunctionValue<'ctxt> {
+        if let Some(dump_fn) = self.module.get_function("bf_dump_tape") {
+            return dump_fn;
+        }
+        let current_block = self.builder.get_insert_block().unwrap();
+        let dump_fn = self.create_dump_fn();
+        self.builder.position_at_end(current_block);
+        dump_fn
+    }
+
+    fn create_dump_fn(&self) -> FunctionValue<'ctxt> {
+        let i8_ptr = self.context.i8_type().ptr_type(AddressSpace::default());
+        let i32_type = self.context.i32_type();
+        let dump_fn_type = self
+            .context
+            .void_type()
+            .fn_type(&[i8_ptr.into(), i32_type.into()], false);
+        let dump_fn =
+            self.module
+                .add_function("bf_dump_tape", dump_fn_type, Some(Linkage::Internal));
+
+        let entry = self.context.append_basic_block(dump_fn, "entry");
+        let loop_head = self.context.append_basic_block(dump_fn, "loop_head");
+        let loop_body = self.context.append_basic_block(dump_fn, "loop_body");
+        let done = self.context.append_basic_block(dump_fn, "done");
+
+        let tape = dump_fn.get_nth_param(0).unwrap().into_pointer_value();
+        let pos = dump_fn.get_nth_param(1).unwrap().into_int_value();
+
+        self.builder.position_at_end(entry);
+        self.build_print("tape pointer: %d\n", &[pos.into()]);
+
+        let start = self
+            .builder
+            .build_int_sub(pos, i32_type.const_int(WINDOW, false), "start");
+        let start_negative = self.builder.build_int_compare(
+            IntPredicate::SLT,
+            start,
+            i32_type.const_zero(),
+            "start_negative",
+        );
+        let start = self
+            .builder
+            .build_select(start_negative, i32_type.const_zero(), start, "start")
+            .into_int_value();
+
+        let tape_size = i32_type.const_int(TAPE_SIZE as u64, false);
+        let end = self
+            .builder
+            .build_int_add(pos, i32_type.const_int(WINDOW + 1, false), "end");
+        let end_past_tape =
+            self.builder
+                .build_int_compare(IntPredicate::SGT, end, tape_size, "end_past_tape");
+        let end = self
+            .builder
+            .build_select(end_past_tape, tape_size, end, "end")
+            .into_int_value();
+
+        self.build_print("cells %d..%d:", &[start.into(), end.into()]);
+        self.builder.build_unconditional_branch(loop_head);
+
+        self.builder.position_at_end(loop_head);
+        let index = self.builder.build_phi(i32_type, "index");
+        let index_value = index.as_basic_value().into_int_value();
+        let in_window =
+            self.builder
+                .build_int_compare(IntPredicate::SLT, index_value, end, "in_window");
+        self.builder
+            .build_conditional_branch(in_window, loop_body, done);
+
+        self.builder.position_at_end(loop_body);
+        let ptr_to_value = unsafe { self.builder.build_gep(tape, &[index_value], "ptr_to_value") };
+        let value = self
+            .builder
+            .build_load(ptr_to_value, "value")
+            .into_int_value();
+        let value = self.builder.build_int_z_extend(value, i32_type, "value");
+        self.build_print(" %d", &[value.into()]);
+        let next_index =
+            self.builder
+                .build_int_add(index_value, i32_type.const_int(1, false), "next_index");
+        self.builder.build_unconditional_branch(loop_head);
+
+        index.add_incoming(&[(&start, entry), (&next_index, loop_body)]);
+
+        self.builder.position_at_end(done);
+        self.build_print("\n", &[]);
+        self.builder.build_return(None);
+
+        dump_fn
+    }
+
+    pub fn build(&self) {
+        let dump_fn = self.get_or_create_dump_fn();
+
+        let current_tape_pos = self
+            .builder
+            .build_load(self.tape_pos, "tape_pos")
+            .into_int_value();
+        self.builder
+            .build_call(dump_fn, &[self.tape.into(), current_tape_pos.into()], "_");
+    }
+}
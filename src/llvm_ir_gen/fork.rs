@@ -0,0 +1,153 @@
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::AddressSpace;
+use inkwell::IntPredicate;
+
+/// Brainfork's `Y` on top of `fork()`: the parent continues with the current
+/// cell set to 0, the child on its copy of the tape one cell further right,
+/// with that cell set to 1.
+pub struct Fork<'ctxt, 'a> {
+    context: &'ctxt Context,
+    module: &'a Module<'ctxt>,
+    builder: &'a Builder<'ctxt>,
+
+    tape: PointerValue<'ctxt>,
+    tape_pos: PointerValue<'ctxt>,
+}
+
+impl<'ctxt, 'a> Fork<'ctxt, 'a> {
+    pub fn new(
+        context: &'ctxt Context,
+        module: &'a Module<'ctxt>,
+        builder: &'a Builder<'ctxt>,
+        tape: PointerValue<'ctxt>,
+        tape_pos: PointerValue<'ctxt>,
+    ) -> Self {
+        Self {
+            context,
+            module,
+            builder,
+            tape,
+            tape_pos,
+        }
+    }
+
+    fn get_function(&self, name: &str, with_pointer_param: bool) -> FunctionValue<'ctxt> {
+        self.module.get_function(name).unwrap_or_else(|| {
+            let i8_ptr = self.context.i8_type().ptr_type(AddressSpace::default());
+            let params = if with_pointer_param {
+                vec![i8_ptr.into()]
+            } else {
+                vec![]
+            };
+            let fn_type = self.context.i32_type().fn_type(&params, false);
+            self.module
+                .add_function(name, fn_type, Some(Linkage::External))
+        })
+    }
+
+    fn store_cell(&self, value: u64) {
+        let current_tape_pos = self
+            .builder
+            .build_load(self.tape_pos, "tape_pos")
+            .into_int_value();
+        let ptr_to_value = unsafe {
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
+        };
+        self.builder
+            .build_store(ptr_to_value, self.context.i8_type().const_int(value, false));
+    }
+
+    pub fn build(&self) {
+        let current_fn = self
+            .builder
+            .get_insert_block()
+            .and_then(|block| block.get_parent())
+            .unwrap();
+        let child_block = self.context.append_basic_block(current_fn, "child");
+        let parent_block = self.context.append_basic_block(current_fn, "parent");
+        let cont_block = self.context.append_basic_block(current_fn, "forked");
+
+        // Unflushed output would otherwise be written by both processes
+        let null = self
+            .context
+            .i8_type()
+            .ptr_type(AddressSpace::default())
+            .const_null();
+        self.builder
+            .build_call(self.get_function("fflush", true), &[null.into()], "_");
+        let pid = self
+            .builder
+            .build_call(self.get_function("fork", false), &[], "pid")
+            .try_as_basic_value()
+            .unwrap_left()
+            .into_int_value();
+        let is_child = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            pid,
+            self.context.i32_type().const_zero(),
+            "is_child",
+        );
+        self.builder
+            .build_conditional_branch(is_child, child_block, parent_block);
+
+        self.builder.position_at_end(child_block);
+        let current_tape_pos = self
+            .builder
+            .build_load(self.tape_pos, "tape_pos")
+            .into_int_value();
+        let next_tape_pos = self.builder.build_int_add(
+            current_tape_pos,
+            self.context.i32_type().const_int(1, false),
+            "tape_pos",
+        );
+        self.builder.build_store(self.tape_pos, next_tape_pos);
+        self.store_cell(1);
+        self.builder.build_unconditional_branch(cont_block);
+
+        self.builder.position_at_end(parent_block);
+        self.store_cell(0);
+        self.builder.build_unconditional_branch(cont_block);
+
+        self.builder.position_at_end(cont_block);
+    }
+
+    /// Waits until all children have exited, so the program only finishes
+    /// once all of its threads have
+    pub fn build_wait_children(&self) {
+        let current_fn = self
+            .builder
+            .get_insert_block()
+            .and_then(|block| block.get_parent())
+            .unwrap();
+        let wait_block = self.context.append_basic_block(current_fn, "wait");
+        let done_block = self.context.append_basic_block(current_fn, "waited");
+
+        self.builder.build_unconditional_branch(wait_block);
+        self.builder.position_at_end(wait_block);
+        let null = self
+            .context
+            .i8_type()
+            .ptr_type(AddressSpace::default())
+            .const_null();
+        let pid = self
+            .builder
+            .build_call(self.get_function("wait", true), &[null.into()], "pid")
+            .try_as_basic_value()
+            .unwrap_left()
+            .into_int_value();
+        let has_children = self.builder.build_int_compare(
+            IntPredicate::SGT,
+            pid,
+            self.context.i32_type().const_zero(),
+            "has_children",
+        );
+        self.builder
+            .build_conditional_branch(has_children, wait_block, done_block);
+
+        self.builder.position_at_end(done_block);
+    }
+}
@@ -0,0 +1,373 @@
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::{FunctionType, StructType};
+use inkwell::values::{
+    BasicMetadataValueEnum, BasicValueEnum, CallSiteValue, CallableValue, FunctionValue, IntValue,
+    PointerValue,
+};
+use inkwell::AddressSpace;
+use inkwell::IntPredicate;
+
+// Fields of the buffer I/O state, see `Io::buffer_state_type`
+const IN: u32 = 0;
+const IN_LEN: u32 = 1;
+const IN_POS: u32 = 2;
+const OUT: u32 = 3;
+const OUT_CAP: u32 = 4;
+const OUT_LEN: u32 = 5;
+const TRUNCATED: u32 = 6;
+
+#[derive(Clone, Copy)]
+enum Callee<'ctxt> {
+    Function(FunctionValue<'ctxt>),
+    Pointer(PointerValue<'ctxt>),
+}
+
+impl<'ctxt> Callee<'ctxt> {
+    fn build_call(
+        self,
+        builder: &Builder<'ctxt>,
+        args: &[BasicMetadataValueEnum<'ctxt>],
+        name: &str,
+    ) -> CallSiteValue<'ctxt> {
+        match self {
+            Callee::Function(function) => builder.build_call(function, args, name),
+            Callee::Pointer(pointer) => {
+                let callable = CallableValue::try_from(pointer).unwrap();
+                builder.build_call(callable, args, name)
+            }
+        }
+    }
+}
+
+/// Functions called for `,` and `.`. Both follow the `getchar`/`putchar`
+/// convention (`-1` on EOF, character passed as `i32`), with `extra_args`
+/// passed before the character.
+#[derive(Clone)]
+pub struct Io<'ctxt> {
+    read_fn: Callee<'ctxt>,
+    write_fn: Callee<'ctxt>,
+    extra_args: Vec<BasicMetadataValueEnum<'ctxt>>,
+}
+
+impl<'ctxt> Io<'ctxt> {
+    pub fn stdio(context: &'ctxt Context, module: &Module<'ctxt>) -> Self {
+        let read_fn = module.get_function("getchar").unwrap_or_else(|| {
+            let getchar_fn_type = context.i32_type().fn_type(&[], false);
+            module.add_function("getchar", getchar_fn_type, Some(Linkage::External))
+        });
+
+        let write_fn = module.get_function("putchar").unwrap_or_else(|| {
+            let putchar_fn_type = context
+                .i32_type()
+                .fn_type(&[context.i32_type().into()], false);
+            module.add_function("putchar", putchar_fn_type, Some(Linkage::External))
+        });
+
+        Io {
+            read_fn: Callee::Function(read_fn),
+            write_fn: Callee::Function(write_fn),
+            extra_args: Vec::new(),
+        }
+    }
+
+    /// Like `stdio`, but reads from `input` compiled into the module, see
+    /// `Extension::InlineInput`
+    pub fn embedded(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        input: &[u8],
+    ) -> Self {
+        let read_fn = Self::build_embedded_read(context, module, builder, input);
+
+        Io {
+            read_fn: Callee::Function(read_fn),
+            ..Self::stdio(context, module)
+        }
+    }
+
+    /// Rewinds the input of `embedded` to its first byte, so each call of a
+    /// cdylib entry point reads it from the start
+    pub fn reset_embedded_input(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+    ) {
+        let input_pos = module.get_global("bf_input_pos").unwrap();
+        builder.build_store(
+            input_pos.as_pointer_value(),
+            context.i64_type().const_zero(),
+        );
+    }
+
+    fn build_embedded_read(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        input: &[u8],
+    ) -> FunctionValue<'ctxt> {
+        let i64_type = context.i64_type();
+        let bytes: Vec<IntValue> = input
+            .iter()
+            .map(|&byte| context.i8_type().const_int(byte as u64, false))
+            .collect();
+        let input_value = context.i8_type().const_array(&bytes);
+        let input_global = module.add_global(input_value.get_type(), None, "bf_input");
+        input_global.set_linkage(Linkage::Internal);
+        input_global.set_constant(true);
+        input_global.set_initializer(&input_value);
+        let input_pos = module.add_global(i64_type, None, "bf_input_pos");
+        input_pos.set_linkage(Linkage::Internal);
+        input_pos.set_initializer(&i64_type.const_zero());
+
+        let read_fn_type = context.i32_type().fn_type(&[], false);
+        let read_fn = module.add_function("bf_read", read_fn_type, Some(Linkage::Internal));
+
+        let entry = context.append_basic_block(read_fn, "entry");
+        let read = context.append_basic_block(read_fn, "read");
+        let eof = context.append_basic_block(read_fn, "eof");
+
+        builder.position_at_end(entry);
+        let in_pos = builder
+            .build_load(input_pos.as_pointer_value(), "in_pos")
+            .into_int_value();
+        let in_len = i64_type.const_int(input.len() as u64, false);
+        let has_input = builder.build_int_compare(IntPredicate::ULT, in_pos, in_len, "has_input");
+        builder.build_conditional_branch(has_input, read, eof);
+
+        builder.position_at_end(read);
+        let char_ptr = unsafe {
+            builder.build_gep(
+                input_global.as_pointer_value(),
+                &[i64_type.const_zero(), in_pos],
+                "char_ptr",
+            )
+        };
+        let char = builder.build_load(char_ptr, "char").into_int_value();
+        let next_pos = builder.build_int_add(in_pos, i64_type.const_int(1, false), "next_pos");
+        builder.build_store(input_pos.as_pointer_value(), next_pos);
+        let char = builder.build_int_z_extend(char, context.i32_type(), "char");
+        builder.build_return(Some(&char));
+
+        builder.position_at_end(eof);
+        builder.build_return(Some(&context.i32_type().const_all_ones()));
+
+        read_fn
+    }
+
+    /// `int (*read)(void *ctx)`
+    pub fn read_callback_type(context: &'ctxt Context) -> FunctionType<'ctxt> {
+        let i8_ptr = context.i8_type().ptr_type(AddressSpace::default());
+        context.i32_type().fn_type(&[i8_ptr.into()], false)
+    }
+
+    /// `int (*write)(void *ctx, int c)`
+    pub fn write_callback_type(context: &'ctxt Context) -> FunctionType<'ctxt> {
+        let i8_ptr = context.i8_type().ptr_type(AddressSpace::default());
+        context
+            .i32_type()
+            .fn_type(&[i8_ptr.into(), context.i32_type().into()], false)
+    }
+
+    /// Calls the function pointers `read` and `write`, passing `ctx` first
+    pub fn callback(
+        read: PointerValue<'ctxt>,
+        write: PointerValue<'ctxt>,
+        ctx: PointerValue<'ctxt>,
+    ) -> Self {
+        Io {
+            read_fn: Callee::Pointer(read),
+            write_fn: Callee::Pointer(write),
+            extra_args: vec![ctx.into()],
+        }
+    }
+
+    /// `{ in, in_len, in_pos, out, out_cap, out_len, truncated }`, where
+    /// `out_len` points to the caller's counter
+    pub fn buffer_state_type(context: &'ctxt Context) -> StructType<'ctxt> {
+        let i8_ptr = context.i8_type().ptr_type(AddressSpace::default());
+        let i64_type = context.i64_type();
+        context.struct_type(
+            &[
+                i8_ptr.into(),
+                i64_type.into(),
+                i64_type.into(),
+                i8_ptr.into(),
+                i64_type.into(),
+                i64_type.ptr_type(AddressSpace::default()).into(),
+                context.i32_type().into(),
+            ],
+            false,
+        )
+    }
+
+    /// Reads from and writes to caller-supplied buffers described by `state`.
+    /// Writes past `out_cap` are dropped and set the `truncated` flag.
+    pub fn buffer(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+        state: PointerValue<'ctxt>,
+    ) -> Self {
+        let read_fn = Self::build_buffer_read(context, module, builder);
+        let write_fn = Self::build_buffer_write(context, module, builder);
+
+        Io {
+            read_fn: Callee::Function(read_fn),
+            write_fn: Callee::Function(write_fn),
+            extra_args: vec![state.into()],
+        }
+    }
+
+    fn build_buffer_read(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+    ) -> FunctionValue<'ctxt> {
+        let state_ptr_type = Self::buffer_state_type(context).ptr_type(AddressSpace::default());
+        let read_fn_type = context.i32_type().fn_type(&[state_ptr_type.into()], false);
+        let read_fn = module.add_function("bf_read", read_fn_type, Some(Linkage::Internal));
+
+        let entry = context.append_basic_block(read_fn, "entry");
+        let read = context.append_basic_block(read_fn, "read");
+        let eof = context.append_basic_block(read_fn, "eof");
+
+        let state = read_fn.get_nth_param(0).unwrap().into_pointer_value();
+
+        builder.position_at_end(entry);
+        let in_pos_ptr = builder
+            .build_struct_gep(state, IN_POS, "in_pos_ptr")
+            .unwrap();
+        let in_pos = builder.build_load(in_pos_ptr, "in_pos").into_int_value();
+        let in_len_ptr = builder
+            .build_struct_gep(state, IN_LEN, "in_len_ptr")
+            .unwrap();
+        let in_len = builder.build_load(in_len_ptr, "in_len").into_int_value();
+        let has_input = builder.build_int_compare(IntPredicate::ULT, in_pos, in_len, "has_input");
+        builder.build_conditional_branch(has_input, read, eof);
+
+        builder.position_at_end(read);
+        let in_ptr = builder.build_struct_gep(state, IN, "in_ptr").unwrap();
+        let input = builder.build_load(in_ptr, "in").into_pointer_value();
+        let char_ptr = unsafe { builder.build_gep(input, &[in_pos], "char_ptr") };
+        let char = builder.build_load(char_ptr, "char").into_int_value();
+        let next_pos =
+            builder.build_int_add(in_pos, context.i64_type().const_int(1, false), "next_pos");
+        builder.build_store(in_pos_ptr, next_pos);
+        let char = builder.build_int_z_extend(char, context.i32_type(), "char");
+        builder.build_return(Some(&char));
+
+        builder.position_at_end(eof);
+        builder.build_return(Some(&context.i32_type().const_all_ones()));
+
+        read_fn
+    }
+
+    fn build_buffer_write(
+        context: &'ctxt Context,
+        module: &Module<'ctxt>,
+        builder: &Builder<'ctxt>,
+    ) -> FunctionValue<'ctxt> {
+        let state_ptr_type = Self::buffer_state_type(context).ptr_type(AddressSpace::default());
+        let write_fn_type = context
+            .i32_type()
+            .fn_type(&[state_ptr_type.into(), context.i32_type().into()], false);
+        let write_fn = module.add_function("bf_write", write_fn_type, Some(Linkage::Internal));
+
+        let entry = context.append_basic_block(write_fn, "entry");
+        let write = context.append_basic_block(write_fn, "write");
+        let full = context.append_basic_block(write_fn, "full");
+
+        let state = write_fn.get_nth_param(0).unwrap().into_pointer_value();
+        let char = write_fn.get_nth_param(1).unwrap().into_int_value();
+
+        builder.position_at_end(entry);
+        let out_len_ptr_ptr = builder
+            .build_struct_gep(state, OUT_LEN, "out_len_ptr_ptr")
+            .unwrap();
+        let out_len_ptr = builder
+            .build_load(out_len_ptr_ptr, "out_len_ptr")
+            .into_pointer_value();
+        let out_len = builder.build_load(out_len_ptr, "out_len").into_int_value();
+        let out_cap_ptr = builder
+            .build_struct_gep(state, OUT_CAP, "out_cap_ptr")
+            .unwrap();
+        let out_cap = builder.build_load(out_cap_ptr, "out_cap").into_int_value();
+        let fits = builder.build_int_compare(IntPredicate::ULT, out_len, out_cap, "fits");
+        builder.build_conditional_branch(fits, write, full);
+
+        builder.position_at_end(write);
+        let out_ptr = builder.build_struct_gep(state, OUT, "out_ptr").unwrap();
+        let output = builder.build_load(out_ptr, "out").into_pointer_value();
+        let char_ptr = unsafe { builder.build_gep(output, &[out_len], "char_ptr") };
+        let byte = builder.build_int_truncate(char, context.i8_type(), "byte");
+        builder.build_store(char_ptr, byte);
+        let next_len =
+            builder.build_int_add(out_len, context.i64_type().const_int(1, false), "next_len");
+        builder.build_store(out_len_ptr, next_len);
+        builder.build_return(Some(&char));
+
+        builder.position_at_end(full);
+        let truncated_ptr = builder
+            .build_struct_gep(state, TRUNCATED, "truncated_ptr")
+            .unwrap();
+        builder.build_store(truncated_ptr, context.i32_type().const_int(1, false));
+        builder.build_return(Some(&context.i32_type().const_all_ones()));
+
+        write_fn
+    }
+
+    /// Fills a buffer I/O state from the `in, in_len, out, out_cap, out_len`
+    /// parameters of `function`, starting at parameter `first_param`.
+    pub fn init_buffer_state(
+        context: &'ctxt Context,
+        builder: &Builder<'ctxt>,
+        state: PointerValue<'ctxt>,
+        function: FunctionValue<'ctxt>,
+        first_param: u32,
+    ) {
+        let param = |i: u32| function.get_nth_param(first_param + i).unwrap();
+        let out_len = param(4).into_pointer_value();
+        let i64_zero = context.i64_type().const_zero();
+
+        let fields: [(u32, BasicValueEnum<'ctxt>); 7] = [
+            (IN, param(0)),
+            (IN_LEN, param(1)),
+            (IN_POS, i64_zero.into()),
+            (OUT, param(2)),
+            (OUT_CAP, param(3)),
+            (OUT_LEN, out_len.into()),
+            (TRUNCATED, context.i32_type().const_zero().into()),
+        ];
+        for (idx, value) in fields {
+            let field_ptr = builder.build_struct_gep(state, idx, "field_ptr").unwrap();
+            builder.build_store(field_ptr, value);
+        }
+        builder.build_store(out_len, i64_zero);
+    }
+
+    pub fn load_truncated(builder: &Builder<'ctxt>, state: PointerValue<'ctxt>) -> IntValue<'ctxt> {
+        let truncated_ptr = builder
+            .build_struct_gep(state, TRUNCATED, "truncated_ptr")
+            .unwrap();
+        builder
+            .build_load(truncated_ptr, "truncated")
+            .into_int_value()
+    }
+
+    pub fn build_read(&self, builder: &Builder<'ctxt>) -> IntValue<'ctxt> {
+        self.read_fn
+            .build_call(builder, &self.extra_args, "input")
+            .try_as_basic_value()
+            .unwrap_left()
+            .into_int_value()
+    }
+
+    pub fn build_write(&self, builder: &Builder<'ctxt>, value: IntValue<'ctxt>) {
+        let mut args = self.extra_args.clone();
+        args.push(value.into());
+        self.write_fn.build_call(builder, &args, "_");
+    }
+}
@@ -3,13 +3,11 @@ use std::cell::Cell;
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
 
 use inkwell::values::PointerValue;
 
 pub struct Loop<'ctxt, 'a> {
     pub context: &'ctxt Context,
-    pub module: &'a Module<'ctxt>,
     pub builder: &'a Builder<'ctxt>,
     pub tape: PointerValue<'ctxt>,
     pub tape_pos: PointerValue<'ctxt>,
@@ -19,14 +17,12 @@ pub struct Loop<'ctxt, 'a> {
 impl<'ctxt, 'a> Loop<'ctxt, 'a> {
     pub fn new(
         context: &'ctxt Context,
-        module: &'a Module<'ctxt>,
         builder: &'a Builder<'ctxt>,
         tape: PointerValue<'ctxt>,
         tape_pos: PointerValue<'ctxt>,
     ) -> Self {
         Self {
             context,
-            module,
             builder,
             tape,
             tape_pos,
@@ -34,20 +30,24 @@ impl<'ctxt, 'a> Loop<'ctxt, 'a> {
         }
     }
 
-    pub fn build_loop_start(&self) -> (BasicBlock, BasicBlock) {
+    pub fn build_loop_start(&self) -> (BasicBlock<'ctxt>, BasicBlock<'ctxt>) {
         let curr_loop_id = self.loop_id.get() + 1;
         self.loop_id.set(curr_loop_id);
 
         let loop_block_name = format!("loop_{curr_loop_id}");
         let cont_block_name = format!("cont_{curr_loop_id}");
 
-        let main_fn = self.module.get_function("main").unwrap();
+        let current_fn = self
+            .builder
+            .get_insert_block()
+            .and_then(|block| block.get_parent())
+            .unwrap();
         let loop_block = self
             .context
-            .append_basic_block(main_fn, loop_block_name.as_str());
+            .append_basic_block(current_fn, loop_block_name.as_str());
         let cont_block = self
             .context
-            .append_basic_block(main_fn, cont_block_name.as_str());
+            .append_basic_block(current_fn, cont_block_name.as_str());
 
         let current_tape_pos = self
             .builder
@@ -55,14 +55,8 @@ impl<'ctxt, 'a> Loop<'ctxt, 'a> {
             .into_int_value();
 
         let ptr_to_value = unsafe {
-            self.builder.build_gep(
-                self.tape,
-                &[
-                    self.context.i32_type().const_int(0, false),
-                    current_tape_pos,
-                ],
-                "ptr_to_value",
-            )
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
         };
         let value = self
             .builder
@@ -90,14 +84,8 @@ impl<'ctxt, 'a> Loop<'ctxt, 'a> {
             .into_int_value();
 
         let ptr_to_value = unsafe {
-            self.builder.build_gep(
-                self.tape,
-                &[
-                    self.context.i32_type().const_int(0, false),
-                    current_tape_pos,
-                ],
-                "ptr_to_value",
-            )
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
         };
         let value = self
             .builder
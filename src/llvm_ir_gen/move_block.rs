@@ -1,31 +1,24 @@
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
 
 use inkwell::values::PointerValue;
 
 pub struct MoveBlock<'ctxt, 'a> {
     context: &'ctxt Context,
-    module: &'a Module<'ctxt>,
     builder: &'a Builder<'ctxt>,
 
-    tape: PointerValue<'ctxt>,
     tape_pos: PointerValue<'ctxt>,
 }
 
 impl<'ctxt, 'a> MoveBlock<'ctxt, 'a> {
     pub fn new(
         context: &'ctxt Context,
-        module: &'a Module<'ctxt>,
         builder: &'a Builder<'ctxt>,
-        tape: PointerValue<'ctxt>,
         tape_pos: PointerValue<'ctxt>,
     ) -> Self {
         Self {
             context,
-            module,
             builder,
-            tape,
             tape_pos,
         }
     }
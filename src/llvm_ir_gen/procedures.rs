@@ -0,0 +1,142 @@
+use std::cell::Cell;
+
+use inkwell::basic_block::BasicBlock;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::FunctionType;
+use inkwell::values::{CallableValue, FunctionValue, GlobalValue, PointerValue};
+use inkwell::AddressSpace;
+
+// One slot for every value of the current cell
+const TABLE_SIZE: u32 = 256;
+
+/// pbrain procedures: every `(...)` becomes a function `bf_proc_N`, whose
+/// address `(` stores in `bf_procs` at the index of the current cell, and `:`
+/// calls through that table. Procedures share the tape and the tape position
+/// with `main`, so both have to be globals.
+pub struct Procedures<'ctxt, 'a> {
+    context: &'ctxt Context,
+    module: &'a Module<'ctxt>,
+    builder: &'a Builder<'ctxt>,
+    tape: PointerValue<'ctxt>,
+    tape_pos: PointerValue<'ctxt>,
+
+    table: GlobalValue<'ctxt>,
+    procedure_id: Cell<u32>,
+}
+
+impl<'ctxt, 'a> Procedures<'ctxt, 'a> {
+    pub fn new(
+        context: &'ctxt Context,
+        module: &'a Module<'ctxt>,
+        builder: &'a Builder<'ctxt>,
+        tape: PointerValue<'ctxt>,
+        tape_pos: PointerValue<'ctxt>,
+    ) -> Self {
+        let table_type = Self::procedure_type(context)
+            .ptr_type(AddressSpace::default())
+            .array_type(TABLE_SIZE);
+        let table = module.add_global(table_type, None, "bf_procs");
+        table.set_linkage(Linkage::Internal);
+        table.set_initializer(&table_type.const_zero());
+
+        Self {
+            context,
+            module,
+            builder,
+            tape,
+            tape_pos,
+            table,
+            procedure_id: Cell::new(0),
+        }
+    }
+
+    /// `void bf_proc_N(void)`
+    fn procedure_type(context: &'ctxt Context) -> FunctionType<'ctxt> {
+        context.void_type().fn_type(&[], false)
+    }
+
+    /// Pointer to the slot of the table numbered by the current cell
+    fn build_slot_ptr(&self) -> PointerValue<'ctxt> {
+        let i32_type = self.context.i32_type();
+        let current_tape_pos = self
+            .builder
+            .build_load(self.tape_pos, "tape_pos")
+            .into_int_value();
+        let ptr_to_value = unsafe {
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
+        };
+        let value = self
+            .builder
+            .build_load(ptr_to_value, "value")
+            .into_int_value();
+        let index = self.builder.build_int_z_extend(value, i32_type, "index");
+        unsafe {
+            self.builder.build_gep(
+                self.table.as_pointer_value(),
+                &[i32_type.const_zero(), index],
+                "slot",
+            )
+        }
+    }
+
+    /// Creates the function of the next procedure and moves the builder to
+    /// its entry. Returns the function and the block to continue the caller in.
+    pub fn build_start(&self) -> (FunctionValue<'ctxt>, BasicBlock<'ctxt>) {
+        let caller_block = self.builder.get_insert_block().unwrap();
+
+        let id = self.procedure_id.get() + 1;
+        self.procedure_id.set(id);
+        let procedure = self.module.add_function(
+            &format!("bf_proc_{id}"),
+            Self::procedure_type(self.context),
+            Some(Linkage::Internal),
+        );
+        let entry = self.context.append_basic_block(procedure, "entry");
+        self.builder.position_at_end(entry);
+
+        (procedure, caller_block)
+    }
+
+    /// Returns from the procedure and moves the builder back to the caller
+    pub fn build_end(&self, caller_block: BasicBlock<'ctxt>) {
+        self.builder.build_return(None);
+        self.builder.position_at_end(caller_block);
+    }
+
+    /// Stores `procedure` in the slot numbered by the current cell
+    pub fn build_define(&self, procedure: FunctionValue<'ctxt>) {
+        let slot = self.build_slot_ptr();
+        self.builder
+            .build_store(slot, procedure.as_global_value().as_pointer_value());
+    }
+
+    /// Calls the procedure numbered by the current cell, if it is defined
+    pub fn build_call(&self) {
+        let current_fn = self
+            .builder
+            .get_insert_block()
+            .and_then(|block| block.get_parent())
+            .unwrap();
+        let call_block = self.context.append_basic_block(current_fn, "call");
+        let cont_block = self.context.append_basic_block(current_fn, "call_cont");
+
+        let slot = self.build_slot_ptr();
+        let procedure = self
+            .builder
+            .build_load(slot, "procedure")
+            .into_pointer_value();
+        let defined = self.builder.build_is_not_null(procedure, "defined");
+        self.builder
+            .build_conditional_branch(defined, call_block, cont_block);
+
+        self.builder.position_at_end(call_block);
+        let callable = CallableValue::try_from(procedure).unwrap();
+        self.builder.build_call(callable, &[], "_");
+        self.builder.build_unconditional_branch(cont_block);
+
+        self.builder.position_at_end(cont_block);
+    }
+}
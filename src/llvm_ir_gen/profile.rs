@@ -0,0 +1,121 @@
+use std::cell::Cell;
+
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{GlobalValue, IntValue};
+use inkwell::AddressSpace;
+
+use crate::profile::Site;
+
+/// Execution counters of `--profile` builds, see `crate::profile`
+pub struct Profile<'ctxt, 'a> {
+    context: &'ctxt Context,
+    module: &'a Module<'ctxt>,
+    builder: &'a Builder<'ctxt>,
+
+    sites: Vec<Site>,
+    counts: GlobalValue<'ctxt>,
+    next_site: Cell<usize>,
+}
+
+impl<'ctxt, 'a> Profile<'ctxt, 'a> {
+    pub fn new(
+        context: &'ctxt Context,
+        module: &'a Module<'ctxt>,
+        builder: &'a Builder<'ctxt>,
+        sites: Vec<Site>,
+    ) -> Self {
+        let counts_type = context.i64_type().array_type(sites.len() as u32);
+        let counts = module.add_global(counts_type, None, "bf_profile_counts");
+        counts.set_linkage(Linkage::Internal);
+        counts.set_initializer(&counts_type.const_zero());
+
+        Self {
+            context,
+            module,
+            builder,
+            sites,
+            counts,
+            next_site: Cell::new(0),
+        }
+    }
+
+    fn const_i32(&self, value: u64) -> IntValue<'ctxt> {
+        self.context.i32_type().const_int(value, false)
+    }
+
+    /// Increments the counter of the next site, see `profile::sites`
+    pub fn build_count(&self) {
+        let site = self.next_site.get();
+        self.next_site.set(site + 1);
+
+        let ptr_to_count = unsafe {
+            self.builder.build_gep(
+                self.counts.as_pointer_value(),
+                &[self.const_i32(0), self.const_i32(site as u64)],
+                "ptr_to_count",
+            )
+        };
+        let count = self
+            .builder
+            .build_load(ptr_to_count, "count")
+            .into_int_value();
+        let count =
+            self.builder
+                .build_int_add(count, self.context.i64_type().const_int(1, false), "count");
+        self.builder.build_store(ptr_to_count, count);
+    }
+
+    /// Calls `bf_profile_report` with the site table and the counters
+    pub fn build_report(&self) {
+        let i32_type = self.context.i32_type();
+        let records: Vec<IntValue> = self
+            .sites
+            .iter()
+            .flat_map(|site| site.record())
+            .map(|value| self.const_i32(value as u64))
+            .collect();
+        let sites_value = i32_type.const_array(&records);
+        let sites = self
+            .module
+            .add_global(sites_value.get_type(), None, "bf_profile_sites");
+        sites.set_linkage(Linkage::Internal);
+        sites.set_constant(true);
+        sites.set_initializer(&sites_value);
+
+        let i32_ptr = i32_type.ptr_type(AddressSpace::default());
+        let i64_ptr = self.context.i64_type().ptr_type(AddressSpace::default());
+        let report_fn = self
+            .module
+            .get_function("bf_profile_report")
+            .unwrap_or_else(|| {
+                let report_fn_type = self.context.void_type().fn_type(
+                    &[
+                        i32_ptr.into(),
+                        i64_ptr.into(),
+                        self.context.i64_type().into(),
+                    ],
+                    false,
+                );
+                self.module.add_function(
+                    "bf_profile_report",
+                    report_fn_type,
+                    Some(Linkage::External),
+                )
+            });
+
+        let sites = self
+            .builder
+            .build_pointer_cast(sites.as_pointer_value(), i32_ptr, "sites");
+        let counts =
+            self.builder
+                .build_pointer_cast(self.counts.as_pointer_value(), i64_ptr, "counts");
+        let n = self
+            .context
+            .i64_type()
+            .const_int(self.sites.len() as u64, false);
+        self.builder
+            .build_call(report_fn, &[sites.into(), counts.into(), n.into()], "_");
+    }
+}
@@ -1,32 +1,33 @@
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
 
 use inkwell::values::PointerValue;
 
+use super::io::Io;
+
 pub struct ReadChar<'ctxt, 'a> {
     context: &'ctxt Context,
-    module: &'a Module<'ctxt>,
     builder: &'a Builder<'ctxt>,
 
     tape: PointerValue<'ctxt>,
     tape_pos: PointerValue<'ctxt>,
+    io: Io<'ctxt>,
 }
 
 impl<'ctxt, 'a> ReadChar<'ctxt, 'a> {
     pub fn new(
         context: &'ctxt Context,
-        module: &'a Module<'ctxt>,
         builder: &'a Builder<'ctxt>,
         tape: PointerValue<'ctxt>,
         tape_pos: PointerValue<'ctxt>,
+        io: Io<'ctxt>,
     ) -> Self {
         Self {
             context,
-            module,
             builder,
             tape,
             tape_pos,
+            io,
         }
     }
 
@@ -37,23 +38,14 @@ impl<'ctxt, 'a> ReadChar<'ctxt, 'a> {
             .into_int_value();
 
         let ptr_to_value = unsafe {
-            self.builder.build_gep(
-                self.tape,
-                &[
-                    self.context.i32_type().const_int(0, false),
-                    current_tape_pos,
-                ],
-                "ptr_to_value",
-            )
+            self.builder
+                .build_gep(self.tape, &[current_tape_pos], "ptr_to_value")
         };
 
-        let getchar_fn = self.module.get_function("getchar").unwrap();
-
+        let input = self.io.build_read(self.builder);
         let input = self
             .builder
-            .build_call(getchar_fn, &[], "input")
-            .try_as_basic_value()
-            .unwrap_left();
+            .build_int_truncate(input, self.context.i8_type(), "input");
 
         self.builder.build_store(ptr_to_value, input);
     }
@@ -1,32 +1,33 @@
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
 
 use inkwell::values::PointerValue;
 
+use super::io::Io;
+
 pub struct WriteChar<'ctxt, 'a> {
     context: &'ctxt Context,
-    module: &'a Module<'ctxt>,
     builder: &'a Builder<'ctxt>,
 
     tape: PointerValue<'ctxt>,
     tape_pos: PointerValue<'ctxt>,
+    io: Io<'ctxt>,
 }
 
 impl<'ctxt, 'a> WriteChar<'ctxt, 'a> {
     pub fn new(
         context: &'ctxt Context,
-        module: &'a Module<'ctxt>,
         builder: &'a Builder<'ctxt>,
         tape: PointerValue<'ctxt>,
         tape_pos: PointerValue<'ctxt>,
+        io: Io<'ctxt>,
     ) -> Self {
         Self {
             context,
-            module,
             builder,
             tape,
             tape_pos,
+            io,
         }
     }
 
@@ -37,19 +38,18 @@ impl<'ctxt, 'a> WriteChar<'ctxt, 'a> {
             .into_int_value();
 
         let ptr_to_value = unsafe {
-            self.builder.build_gep(
-                self.tape,
-                &[self.context.i32_type().const_int(0, false), current_pos],
-                "ptr_to_value",
-            )
+            self.builder
+                .build_gep(self.tape, &[current_pos], "ptr_to_value")
         };
         let value = self
             .builder
             .build_load(ptr_to_value, "value")
             .into_int_value();
 
-        let putchar_fn = self.module.get_function("putchar").unwrap();
+        let value = self
+            .builder
+            .build_int_z_extend(value, self.context.i32_type(), "value");
 
-        self.builder.build_call(putchar_fn, &[value.into()], "_");
+        self.io.build_write(self.builder, value);
     }
 }
@@ -1,14 +1,12 @@
+use brainfc::linker::Linker;
+use brainfc::{Backend, BrainfcError};
 use clap::Parser;
 use colored::Colorize;
-use optimizer::Optimizer;
-use std::path::PathBuf;
 
-pub mod app;
-pub mod lexer;
-pub mod llvm_ir_gen;
-pub mod optimizations;
-pub mod optimizer;
-pub mod parser;
+mod app;
+mod bench;
+mod debugger;
+mod golden;
 
 fn report_error(msg: String) {
     eprintln!("{} {}", "error: ".red().bold(), msg);
@@ -18,80 +16,103 @@ fn report_info(msg: String) {
     eprintln!("{} {}", "info: ".bold(), msg);
 }
 
-fn main() {
-    std_logger::Config::logfmt().init();
-
-    let args = app::Args::parse();
-    log::debug!("parsed args: {:?}", args);
+fn run(args: app::Args) -> Result<(), BrainfcError> {
+    match args.command {
+        Some(app::Command::Debug(debug_args)) => debugger::run(debug_args),
+        Some(app::Command::GenPrint(gen_args)) => gen_print(gen_args),
+        Some(app::Command::Test(test_args)) => golden::run(test_args),
+        Some(app::Command::Bench(bench_args)) => bench::run(bench_args),
+        None => compile(args),
+    }
+}
 
-    let Ok(program_text) = std::fs::read_to_string(&args.input) else {
-        report_error(format!("Could not open {:?}", args.input));
-        return;
+fn gen_print(args: app::GenPrintArgs) -> Result<(), BrainfcError> {
+    let mut text = match (args.text, &args.file) {
+        (Some(text), _) => std::os::unix::ffi::OsStringExt::into_vec(text),
+        (None, Some(path)) if path.as_os_str() == "-" => {
+            let mut text = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut text)
+                .map_err(|e| BrainfcError::io("Could not read stdin", e))?;
+            text
+        }
+        (None, Some(path)) => std::fs::read(path)
+            .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?,
+        (None, None) => unreachable!("clap requires the text or a file"),
     };
+    if args.newline {
+        text.push(b'\n');
+    }
+    let program = brainfc::generator::print_program(&text)?;
+    match &args.output {
+        Some(output) => {
+            std::fs::write(output, program + "\n")
+                .map_err(|e| BrainfcError::io(format!("Could not write {output:?}"), e))?;
+            report_info(format!("Done ({})", output.display()));
+        }
+        None => println!("{program}"),
+    }
+    Ok(())
+}
 
-    report_info("Parsing...".to_string());
-    let parsed = parser::parse(lexer::parse(&program_text));
-    if let Err(msg) = parsed {
-        report_error(msg);
-        return;
+fn compile(args: app::Args) -> Result<(), BrainfcError> {
+    let input = args.input.as_ref().unwrap();
+    let (program_text, source_map) = app::read_source(input, &args.extensions)?;
+    if args.show_expanded {
+        println!("{program_text}");
     }
-    let parsed = parsed.unwrap();
 
+    report_info("Parsing...".to_string());
+    let (parsed, inline_input) = app::parse_source(
+        input,
+        &program_text,
+        args.dialect.as_deref(),
+        &args.extensions,
+    )
+    .map_err(|e| match &source_map {
+        Some(map) => map.map_error(e),
+        None => e,
+    })?;
     if args.show_parsed {
         println!("{parsed:?}");
     }
 
     report_info("Optimizing...".to_string());
-    let optimized = Optimizer::new().optimize(parsed);
+    let optimized = brainfc::optimize(parsed);
     if args.show_optimized {
         println!("{optimized:?}");
     }
 
-    let output_file = args
-        .output
-        .as_ref()
-        .cloned()
-        .unwrap_or(PathBuf::new().with_file_name("out"));
-
-    let object_file = output_file.with_extension("o");
+    let mut options = args.compile_options();
+    if let Some(input) = inline_input {
+        options = options.embedded_input(input);
+    }
 
     report_info("Compiling...".to_string());
-    llvm_ir_gen::compile(optimized, args);
-
-    report_info("Linking with gcc...".to_string());
-    std::process::Command::new("gcc")
-        .args([
-            object_file.to_str().unwrap(),
-            "-o",
-            output_file.to_str().unwrap(),
-        ])
-        .spawn()
-        .unwrap()
-        .wait()
-        .unwrap();
-
-    report_info(format!(
-        "Done ({})",
-        output_file.as_path().as_os_str().to_str().unwrap()
-    ));
+    let object_file = brainfc::compile_to_object(optimized, &options)?;
+
+    if options.get_backend() == Backend::Wasm || args.no_link {
+        report_info(format!("Done ({})", object_file.display()));
+        return Ok(());
+    }
+
+    let linker = Linker::resolve(&options)?;
+    report_info(format!("Linking with {linker}..."));
+    let output_file = linker.link(&object_file, &options)?;
+    std::fs::remove_file(&object_file)
+        .map_err(|e| BrainfcError::io(format!("Could not remove {object_file:?}"), e))?;
+
+    report_info(format!("Done ({})", output_file.display()));
+    Ok(())
 }
 
-#[cfg(test)]
-mod test {
-
-    macro_rules! build_ir_test {
-        ($name:ident) => {
-            #[test]
-            pub fn $name() {
-                let input =
-                    std::fs::read_to_string(format!("tests/ir_tests/{}.input", stringify!($name)))
-                        .unwrap();
-                let expected_output =
-                    std::fs::read_to_string(format!("tests/ir_tests/{}.output", stringify!($name)))
-                        .unwrap();
-                let parsed = parser::parse(lexer::parse(&input)).unwrap();
-                assert_eq!(format!("{:?}\n", parsed), expected_output);
-            }
-        };
+fn main() {
+    std_logger::Config::logfmt().init();
+
+    let args = app::Args::parse();
+    log::debug!("parsed args: {:?}", args);
+
+    if let Err(err) = run(args) {
+        report_error(err.to_string());
+        std::process::exit(err.exit_code());
     }
 }
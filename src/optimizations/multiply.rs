@@ -1,3 +1,4 @@
+use crate::cdylib::TAPE_SIZE;
 use crate::lexer::Op;
 use crate::optimizations::base::Optimization;
 use crate::parser::Block;
@@ -6,66 +7,69 @@ pub struct MultiplyOpt;
 
 impl Optimization for MultiplyOpt {
     fn apply(&self, block: &Block) -> Option<Block> {
-        let Block::Loop(subblocks) = block else {
+        let Block::Loop(subblocks, span) = block else {
             return None;
         };
 
-        let [Block::Simple(ref ops)] = subblocks[..] else {
+        let [Block::Simple(ref tokens)] = subblocks[..] else {
             return None;
         };
+        let ops: Vec<&Op> = tokens.iter().map(|token| &token.op).collect();
 
-        let sum_of_moves = ops.iter().fold(0, |acc, op| match op {
-            Op::Move(x) => acc + x,
-            _ => acc,
-        });
-        if sum_of_moves != 0 {
+        let is_all_moves_and_mods = ops
+            .iter()
+            .all(|op| matches!(op, Op::Move(_) | Op::Modify(_)));
+        if !is_all_moves_and_mods {
             return None;
         }
 
-        let is_all_moves_and_mods = ops.iter().all(|op| match op {
-            Op::Move(_) => true,
-            Op::Modify(_) => true,
-            _ => false,
-        });
-        if is_all_moves_and_mods {
-            let max_cell_distance = ops.iter().fold(0, |acc, op| match op {
-                Op::Move(x) => acc + x.abs(),
-                _ => acc,
-            });
-
-            // Initiate an array and record all call modifications which happen in the loop
-            let mut pos = max_cell_distance as usize;
-            let mut init = vec![0; pos + 1];
-            init.append(&mut vec![0; pos]);
-
-            for op in ops {
-                match op {
-                    Op::Move(x) => pos = ((pos as i16) + x) as usize,
-                    Op::Modify(x) => init[pos] += x,
-                    _ => unreachable!(),
-                }
+        // Offsets from the counter, in i64 since runs of moves add up beyond i16
+        let mut offset: i64 = 0;
+        let mut max_cell_distance: i64 = 0;
+        for op in &ops {
+            if let Op::Move(x) = op {
+                offset += *x as i64;
+                max_cell_distance = max_cell_distance.max(offset.abs());
             }
+        }
+        if offset != 0 {
+            return None;
+        }
+        // The loop leaves the tape, which only the loop itself reports
+        if max_cell_distance >= TAPE_SIZE as i64 {
+            return None;
+        }
 
-            if init[max_cell_distance as usize] != -1 {
-                // loop must reset base value
-                return None;
+        // Initiate an array and record all call modifications which happen in the loop
+        let center = max_cell_distance as usize;
+        let mut pos = center;
+        let mut init = vec![0i64; 2 * center + 1];
+        for op in &ops {
+            match op {
+                Op::Move(x) => pos = (pos as i64 + *x as i64) as usize,
+                Op::Modify(x) => init[pos] += *x as i64,
+                _ => unreachable!(),
             }
+        }
 
-            let ops = init
-                .into_iter()
-                .enumerate()
-                .filter(|(_i, val)| *val != 0)
-                .filter(|(i, _val)| *i != max_cell_distance as usize)
-                .map(|(i, val)| ((i as i32 - max_cell_distance as i32), val as i32))
-                .collect::<Vec<(i32, i32)>>();
-
-            let has_negative_mods = ops.iter().any(|(i, _val)| *i < 0);
-            if has_negative_mods {
-                return None;
-            }
-            return Some(Block::Multiply { ops });
+        if init[center] != -1 {
+            // loop must reset base value
+            return None;
         }
 
-        None
+        // Cells wrap around, so truncating keeps the value modulo 256
+        let ops = init
+            .into_iter()
+            .enumerate()
+            .filter(|(_i, val)| *val != 0)
+            .filter(|(i, _val)| *i != center)
+            .map(|(i, val)| ((i as i32 - center as i32), val as i32))
+            .collect::<Vec<(i32, i32)>>();
+
+        let has_negative_mods = ops.iter().any(|(i, _val)| *i < 0);
+        if has_negative_mods {
+            return None;
+        }
+        Some(Block::Multiply { ops, span: *span })
     }
 }
@@ -5,7 +5,7 @@ use crate::parser::Block;
 pub struct ResetValOpt;
 impl Optimization for ResetValOpt {
     fn apply(&self, block: &Block) -> Option<Block> {
-        let Block::Loop(subblocks) = block else {
+        let Block::Loop(subblocks, span) = block else {
             return None
         };
 
@@ -17,8 +17,11 @@ impl Optimization for ResetValOpt {
             return None
         };
 
-        if let [Op::Modify(-1)] = ops[..] {
-            return Some(Block::Reset { offset: 0 });
+        if ops.len() == 1 && ops[0].op == Op::Modify(-1) {
+            return Some(Block::Reset {
+                offset: 0,
+                span: *span,
+            });
         }
 
         None
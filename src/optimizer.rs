@@ -2,14 +2,44 @@ use crate::optimizations::base::*;
 use crate::optimizations::{MultiplyOpt, ResetValOpt};
 use crate::parser::{Block, Program};
 
+/// The optimizations `Optimizer::with_passes` can pick from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    ResetVal,
+    Multiply,
+}
+
+impl Pass {
+    /// Every pass, in the order `Optimizer::new` applies them
+    pub const ALL: [Pass; 2] = [Pass::ResetVal, Pass::Multiply];
+
+    fn optimization(self) -> Box<dyn Optimization> {
+        match self {
+            Pass::ResetVal => Box::new(ResetValOpt {}),
+            Pass::Multiply => Box::new(MultiplyOpt),
+        }
+    }
+}
+
 pub struct Optimizer {
     opts: Vec<Box<dyn Optimization>>,
 }
 
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Optimizer {
     pub fn new() -> Self {
+        Self::with_passes(&Pass::ALL)
+    }
+
+    /// An optimizer applying only `passes`, the first that matches a block wins
+    pub fn with_passes(passes: &[Pass]) -> Self {
         Optimizer {
-            opts: vec![Box::new(ResetValOpt {}), Box::new(MultiplyOpt)],
+            opts: passes.iter().map(|pass| pass.optimization()).collect(),
         }
     }
 
@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{BrainfcError, Result};
+
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    #[cfg(feature = "llvm")]
+    Llvm,
+    Asm,
+    Wasm,
+}
+
+impl Default for Backend {
+    #[cfg(feature = "llvm")]
+    fn default() -> Self {
+        Backend::Llvm
+    }
+
+    #[cfg(not(feature = "llvm"))]
+    fn default() -> Self {
+        Backend::Asm
+    }
+}
+
+impl Backend {
+    /// Whether the generated code depends on `CompileOptions::opt_level`
+    pub fn uses_opt_level(self) -> bool {
+        match self {
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WasmAbi {
+    /// Imports `env.read` and `env.write` and exports `run`
+    #[default]
+    Env,
+    /// Imports `fd_read` and `fd_write` from WASI and exports `_start`
+    Wasi,
+}
+
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrateType {
+    /// Executable with a `main` function
+    #[default]
+    Bin,
+    /// Shared library exporting `bf_run` and `bf_run_tape`, plus a C header
+    Cdylib,
+}
+
+/// Where `,` reads from and `.` writes to
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoMode {
+    /// `getchar` and `putchar`, the default for executables
+    Stdio,
+    /// Caller-supplied input and output buffers, the default for shared libraries
+    Buffer,
+    /// `read` and `write` function pointers passed to the entry point
+    Callback,
+}
+
+/// What executables return when the program finishes
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Always 0
+    #[default]
+    Zero,
+    /// The value of the current cell
+    Cell,
+}
+
+/// How much LLVM optimizes the generated machine code. The other backends
+/// emit the same code at every level.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    None,
+    Less,
+    #[default]
+    Default,
+    Aggressive,
+}
+
+/// Opt-in additions to the language, see `lexer::parse_with`
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    /// `#` prints the tape pointer and the cells around it to stderr
+    DebugDump,
+    /// `!` ends the program, the text after it is the program's input
+    InlineInput,
+    /// `@define`, `@include` and `+*65` repetition, see `preprocessor`
+    Macros,
+}
+
+/// Settings for the code generation and linking stages.
+///
+/// ```no_run
+/// use brainfc::{Backend, CompileOptions};
+///
+/// let options = CompileOptions::new()
+///     .backend(Backend::Asm)
+///     .output("hello");
+/// ```
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    pub(crate) backend: Backend,
+    pub(crate) crate_type: CrateType,
+    pub(crate) wasm_abi: WasmAbi,
+    pub(crate) io: Option<IoMode>,
+    pub(crate) exit_code: ExitCode,
+    pub(crate) opt_level: OptLevel,
+    pub(crate) dump_tape_at_exit: bool,
+    pub(crate) debug_source: Option<PathBuf>,
+    pub(crate) profile: bool,
+    pub(crate) embedded_input: Option<Vec<u8>>,
+    pub(crate) output: PathBuf,
+    pub(crate) show_ir: bool,
+    pub(crate) linker: Option<PathBuf>,
+    pub(crate) link_args: Vec<String>,
+    pub(crate) static_linking: bool,
+    pub(crate) freestanding: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            backend: Backend::default(),
+            crate_type: CrateType::default(),
+            wasm_abi: WasmAbi::default(),
+            io: None,
+            exit_code: ExitCode::default(),
+            opt_level: OptLevel::default(),
+            dump_tape_at_exit: false,
+            debug_source: None,
+            profile: false,
+            embedded_input: None,
+            output: PathBuf::new().with_file_name("out"),
+            show_ir: false,
+            linker: None,
+            link_args: Vec::new(),
+            static_linking: false,
+            freestanding: false,
+        }
+    }
+}
+
+impl CompileOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn crate_type(mut self, crate_type: CrateType) -> Self {
+        self.crate_type = crate_type;
+        self
+    }
+
+    pub fn wasm_abi(mut self, wasm_abi: WasmAbi) -> Self {
+        self.wasm_abi = wasm_abi;
+        self
+    }
+
+    /// I/O of the generated code. Executables only support `IoMode::Stdio`.
+    pub fn io(mut self, io: IoMode) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    pub fn exit_code(mut self, exit_code: ExitCode) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
+    }
+
+    /// Print the position of the tape pointer and the cells around it to
+    /// stderr when the program finishes.
+    pub fn dump_tape_at_exit(mut self, dump_tape_at_exit: bool) -> Self {
+        self.dump_tape_at_exit = dump_tape_at_exit;
+        self
+    }
+
+    /// Emit DWARF line info mapping the generated code back to `source`,
+    /// the file the program was read from.
+    pub fn debug_info(mut self, source: impl Into<PathBuf>) -> Self {
+        self.debug_source = Some(source.into());
+        self
+    }
+
+    /// Count how often every block and loop runs and print a report with
+    /// their source positions to stderr when the program finishes.
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Compile `input` into the program and read `,` from it instead of
+    /// stdin, see `Extension::InlineInput`.
+    pub fn embedded_input(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.embedded_input = Some(input.into());
+        self
+    }
+
+    /// Path of the final artifact. Intermediate files are placed next to it.
+    pub fn output(mut self, output: impl Into<PathBuf>) -> Self {
+        self.output = output.into();
+        self
+    }
+
+    /// Print the backend's intermediate representation (LLVM IR, assembly
+    /// or WAT) to stderr.
+    pub fn show_ir(mut self, show_ir: bool) -> Self {
+        self.show_ir = show_ir;
+        self
+    }
+
+    /// Linker to use instead of the first of `cc`, `clang`, `gcc` and
+    /// `ld.lld` found on `PATH`. Names starting with `ld` are invoked as bare
+    /// linkers, anything else as a compiler driver.
+    pub fn linker(mut self, linker: impl Into<PathBuf>) -> Self {
+        self.linker = Some(linker.into());
+        self
+    }
+
+    /// Extra argument passed to the linker after the object file
+    pub fn link_arg(mut self, arg: impl Into<String>) -> Self {
+        self.link_args.push(arg.into());
+        self
+    }
+
+    pub fn static_linking(mut self, static_linking: bool) -> Self {
+        self.static_linking = static_linking;
+        self
+    }
+
+    /// Generate a `_start` entry point and raw Linux syscalls instead of
+    /// relying on libc, and link a static executable without the C runtime.
+    pub fn freestanding(mut self, freestanding: bool) -> Self {
+        self.freestanding = freestanding;
+        self
+    }
+
+    pub fn get_backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn get_crate_type(&self) -> CrateType {
+        self.crate_type
+    }
+
+    /// The I/O mode, defaulting by crate type if none was set
+    pub fn get_io(&self) -> IoMode {
+        self.io.unwrap_or(match self.crate_type {
+            CrateType::Bin => IoMode::Stdio,
+            CrateType::Cdylib => IoMode::Buffer,
+        })
+    }
+
+    pub fn get_output(&self) -> &Path {
+        &self.output
+    }
+
+    /// The linked executable or library. Shared libraries get a `.so`
+    /// extension unless the output path already has one.
+    pub fn artifact(&self) -> PathBuf {
+        match self.crate_type {
+            CrateType::Cdylib if self.output.extension().is_none() => {
+                self.output.with_extension("so")
+            }
+            _ => self.output.clone(),
+        }
+    }
+
+    /// Rejects combinations of options no backend supports
+    pub(crate) fn validate(&self) -> Result<()> {
+        let error = match self.crate_type {
+            CrateType::Cdylib if self.freestanding => {
+                "--freestanding cannot be combined with --crate-type cdylib"
+            }
+            CrateType::Cdylib if self.exit_code != ExitCode::Zero || self.dump_tape_at_exit => {
+                "--exit-code and --dump-tape-at-exit only apply to executables"
+            }
+            CrateType::Cdylib if self.profile => "--profile only applies to executables",
+            CrateType::Bin if self.get_io() != IoMode::Stdio => {
+                "executables only support --io stdio, use --crate-type cdylib"
+            }
+            _ if self.freestanding && self.dump_tape_at_exit => {
+                "--dump-tape-at-exit needs libc and cannot be combined with --freestanding"
+            }
+            _ if self.embedded_input.is_some() && self.get_io() != IoMode::Stdio => {
+                "inline input only applies to --io stdio"
+            }
+            _ if self.freestanding && self.profile => {
+                "--profile needs libc and cannot be combined with --freestanding"
+            }
+            _ => return Ok(()),
+        };
+        Err(BrainfcError::Codegen(error.to_string()))
+    }
+
+    pub(crate) fn object_file(&self) -> PathBuf {
+        self.output.with_extension("o")
+    }
+}
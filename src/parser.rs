@@ -1,16 +1,41 @@
-use super::lexer::Op;
+use super::lexer::{Op, Pos, Token};
+use crate::error::BrainfcError;
 use log::debug;
 
+/// Positions of the `[` and `]` of a loop, or the `(` and `)` of a procedure
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 #[derive(Debug)]
 pub enum Block {
     // Blocks coming from parser
-    Simple(Vec<Op>),
-    Loop(Program),
-
-    // Blocks coming from optimizer
-    Reset { offset: i32 },
-    JmpLoop { jmp_size: i8 },
-    Multiply { ops: Vec<(i32, i32)> },
+    Simple(Vec<Token>),
+    Loop(Program, Span),
+    /// `(...)` of `Dialect::Pbrain`: makes the body the procedure numbered
+    /// by the current cell, which `:` calls
+    Procedure(Program, Span),
+
+    // Blocks coming from optimizer, `span` is the loop they replace
+    Reset {
+        offset: i32,
+        span: Span,
+    },
+    JmpLoop {
+        jmp_size: i8,
+    },
+    Multiply {
+        ops: Vec<(i32, i32)>,
+        span: Span,
+    },
 }
 impl Block {
     fn empty() -> Block {
@@ -18,99 +43,167 @@ impl Block {
     }
 
     pub fn is_loop(&self) -> bool {
+        matches!(self, Block::Loop(..))
+    }
+
+    /// Whether `f` holds for an operation of the block or of a nested loop
+    /// or procedure
+    pub fn any_op(&self, f: &impl Fn(&Op) -> bool) -> bool {
         match self {
-            Block::Loop(_) => true,
+            Block::Simple(tokens) => tokens.iter().any(|token| f(&token.op)),
+            Block::Loop(body, _) | Block::Procedure(body, _) => {
+                body.iter().any(|block| block.any_op(f))
+            }
             _ => false,
         }
     }
 
+    /// Applies `f` to the blocks of a loop or procedure body
     pub fn map_loop(self, f: impl Fn(Block) -> Block) -> Block {
-        if let Block::Loop(subblocks) = self {
-            return Block::Loop(subblocks.into_iter().map(f).collect());
+        match self {
+            Block::Loop(subblocks, span) => {
+                Block::Loop(subblocks.into_iter().map(f).collect(), span)
+            }
+            Block::Procedure(subblocks, span) => {
+                Block::Procedure(subblocks.into_iter().map(f).collect(), span)
+            }
+            block => block,
         }
-
-        self
     }
 }
 
 pub type Program = Vec<Block>;
 
+pub fn contains_op(program: &Program, op: &Op) -> bool {
+    program
+        .iter()
+        .any(|block| block.any_op(&|other| other == op))
+}
+
+/// Whether `program` defines or calls pbrain procedures
+pub fn uses_procedures(program: &Program) -> bool {
+    contains_op(program, &Op::Call)
+        || program.iter().any(|block| match block {
+            Block::Procedure(..) => true,
+            Block::Loop(body, _) => uses_procedures(body),
+            _ => false,
+        })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Nesting {
+    Loop,
+    Procedure,
+}
+
 struct ProgramBuilder {
     parsing_stack: Vec<Program>,
-    err: Option<String>,
+    // Kind and position of the `[` or `(` of every body on the parsing stack
+    loop_starts: Vec<(Nesting, Pos)>,
+    err: Option<BrainfcError>,
 }
 
 impl ProgramBuilder {
     fn new() -> ProgramBuilder {
         ProgramBuilder {
             parsing_stack: vec![Program::new()],
+            loop_starts: Vec::new(),
             err: None,
         }
     }
 
-    fn add(&mut self, operation: Op) {
+    fn add(&mut self, token: Token) {
         use Op::*;
 
         if self.err.is_some() {
             return;
         }
 
-        match operation {
-            LBr => self.start_loop(),
-            RBr => self.finish_loop(),
-            t => self.add_to_latest_block(t),
+        match token.op {
+            LBr => self.start_loop(Nesting::Loop, token.pos),
+            RBr => self.finish_loop(Nesting::Loop, token.pos),
+            ProcStart => self.start_loop(Nesting::Procedure, token.pos),
+            ProcEnd => self.finish_loop(Nesting::Procedure, token.pos),
+            _ => self.add_to_latest_block(token),
         }
     }
 
-    fn add_to_latest_block(&mut self, t: Op) {
+    fn add_to_latest_block(&mut self, t: Token) {
         debug!("add_to_latest_block: {:?}", t);
 
         let Some(latest_program) = self.parsing_stack.last_mut() else {
-            self.err = Some("Unexpected empty parsing stack".to_string());
+            self.err = Some(BrainfcError::Parse(
+                "Unexpected empty parsing stack".to_string(),
+            ));
             return;
         };
 
-        if latest_program.is_empty() || latest_program.last().unwrap().is_loop() {
+        if !matches!(latest_program.last(), Some(Block::Simple(_))) {
             latest_program.push(Block::empty())
         }
 
         let Block::Simple(latest_block) = latest_program.last_mut().unwrap() else {
-            self.err = Some("Failed to handle loop".to_string());
+            self.err = Some(BrainfcError::Parse("Failed to handle loop".to_string()));
             return;
         };
 
         latest_block.push(t);
     }
 
-    fn finish_loop(&mut self) {
-        let finished_loop: Program = self.parsing_stack.pop().unwrap();
+    fn finish_loop(&mut self, nesting: Nesting, end: Pos) {
+        let Some((started, start)) = self.loop_starts.pop() else {
+            self.err = Some(BrainfcError::Parse("Unmatched closing bracket".to_string()).at(end));
+            return;
+        };
+        if started != nesting {
+            self.err = Some(
+                BrainfcError::Parse("Mismatched brackets".to_string())
+                    .at(start)
+                    .at(end),
+            );
+            return;
+        }
+        let finished_loop = self.parsing_stack.pop().unwrap_or_default();
         debug!("finished loop: {:?}", finished_loop);
-        self.parsing_stack
-            .last_mut()
-            .unwrap()
-            .push(Block::Loop(finished_loop))
+        let Some(parent) = self.parsing_stack.last_mut() else {
+            self.err = Some(BrainfcError::Parse(
+                "Unexpected empty parsing stack".to_string(),
+            ));
+            return;
+        };
+        let span = Span { start, end };
+        parent.push(match nesting {
+            Nesting::Loop => Block::Loop(finished_loop, span),
+            Nesting::Procedure => Block::Procedure(finished_loop, span),
+        })
     }
 
-    fn start_loop(&mut self) {
+    fn start_loop(&mut self, nesting: Nesting, start: Pos) {
         debug!("started loop");
         self.parsing_stack.push(Program::new());
+        self.loop_starts.push((nesting, start));
     }
 
-    fn finalize(mut self) -> Result<Program, String> {
+    fn finalize(mut self) -> Result<Program, BrainfcError> {
         if let Some(err) = self.err {
             return Err(err);
         }
+        if let Some((_, start)) = self.loop_starts.last() {
+            return Err(BrainfcError::Parse("Unmatched opening bracket".to_string()).at(*start));
+        }
         if self.parsing_stack.len() == 1 {
-            self.parsing_stack
-                .pop()
-                .ok_or("Unexpected empty parsing stack".to_string())
+            self.parsing_stack.pop().ok_or(BrainfcError::Parse(
+                "Unexpected empty parsing stack".to_string(),
+            ))
         } else {
-            Err("Parsing finalized before clearing parsing stack.".to_string())
+            Err(BrainfcError::Parse(
+                "Parsing finalized before clearing parsing stack.".to_string(),
+            ))
         }
     }
 }
 
-pub fn parse(tokens: Vec<Op>) -> Result<Program, String> {
+pub fn parse(tokens: Vec<Token>) -> Result<Program, BrainfcError> {
     let mut builder = ProgramBuilder::new();
     tokens.into_iter().for_each(|t| builder.add(t));
     builder.finalize()
@@ -0,0 +1,548 @@
+//! Macro preprocessor of `Extension::Macros`. Expands the directives below
+//! into plain source text, so lexing and everything after it is unchanged:
+//!
+//! ```text
+//! @define clear { [-] }
+//! @define add(n, c) { >+*$n<$c }
+//! @include "lib/print.bf"
+//!
+//! +*65 @add(3, .) @clear @clear*2
+//! ```
+//!
+//! `@define` takes the body between balanced braces, in which `$param` is
+//! replaced by the argument text. `@name` expands a macro, `c*N` repeats the
+//! character or macro call before it `N` times, and `@include` pastes a file
+//! given relative to the including one. Every character of the expansion
+//! remembers the file and position it was written at, see `SourceMap`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::{BrainfcError, Location, Result};
+use crate::lexer::Pos;
+
+/// Characters an expansion, or any macro call in it, may grow to
+const MAX_EXPANSION: usize = 1 << 22;
+
+#[derive(Clone, Copy)]
+struct Origin {
+    file: usize,
+    pos: Pos,
+}
+
+type Char = (char, Origin);
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Char>,
+}
+
+/// Maps positions in an expanded program back to the files it came from
+pub struct SourceMap {
+    files: Vec<PathBuf>,
+    /// Origin of every character of every line of the expansion
+    lines: Vec<Vec<Origin>>,
+}
+
+impl SourceMap {
+    /// Where the character at `pos` of the expansion was written
+    pub fn locate(&self, pos: Pos) -> Option<Location> {
+        let line = self.lines.get((pos.line as usize).checked_sub(1)?)?;
+        let origin = line.get((pos.col as usize).checked_sub(1)?)?;
+        Some(Location {
+            file: Some(self.files[origin.file].clone()),
+            pos: origin.pos,
+        })
+    }
+
+    /// Maps the locations `err` is about, which refer to the expansion, to
+    /// where they were written
+    pub fn map_error(&self, err: BrainfcError) -> BrainfcError {
+        let BrainfcError::At { error, locations } = err else {
+            return err;
+        };
+        let locations = locations
+            .into_iter()
+            .map(|location| match location.file {
+                None => self.locate(location.pos).unwrap_or(location),
+                Some(_) => location,
+            })
+            .collect();
+        BrainfcError::At { error, locations }
+    }
+}
+
+/// The preprocessed program and where each of its characters came from
+pub struct Expansion {
+    pub source: String,
+    pub map: SourceMap,
+}
+
+struct Expander {
+    files: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    /// Canonical and given path of the files being included, outermost
+    /// first, to detect cycles
+    includes: Vec<(PathBuf, PathBuf)>,
+    /// Macros being expanded, to detect recursion
+    calls: Vec<String>,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Reads an identifier at `text[*i..]`
+fn ident(text: &[Char], i: &mut usize) -> String {
+    let start = *i;
+    while *i < text.len() && is_ident_char(text[*i].0) {
+        *i += 1;
+    }
+    text[start..*i].iter().map(|(c, _)| c).collect()
+}
+
+fn skip_whitespace(text: &[Char], i: &mut usize) {
+    while *i < text.len() && text[*i].0.is_whitespace() {
+        *i += 1;
+    }
+}
+
+fn trim(mut text: &[Char]) -> &[Char] {
+    while let [(c, _), rest @ ..] = text {
+        if !c.is_whitespace() {
+            break;
+        }
+        text = rest;
+    }
+    while let [rest @ .., (c, _)] = text {
+        if !c.is_whitespace() {
+            break;
+        }
+        text = rest;
+    }
+    text
+}
+
+impl Expander {
+    fn error(&self, origin: Origin, msg: impl std::fmt::Display) -> BrainfcError {
+        BrainfcError::Parse(msg.to_string()).at(Location {
+            file: Some(self.files[origin.file].clone()),
+            pos: origin.pos,
+        })
+    }
+
+    /// Reads `*N` at `text[*i..]`, if present
+    fn repetition(&self, text: &[Char], i: &mut usize) -> Result<Option<usize>> {
+        let Some(&('*', origin)) = text.get(*i) else {
+            return Ok(None);
+        };
+        let mut end = *i + 1;
+        while end < text.len() && text[end].0.is_ascii_digit() {
+            end += 1;
+        }
+        if end == *i + 1 {
+            return Ok(None);
+        }
+        let digits: String = text[*i + 1..end].iter().map(|(c, _)| c).collect();
+        let count = digits
+            .parse()
+            .ok()
+            .filter(|&count| count <= MAX_EXPANSION)
+            .ok_or_else(|| {
+                self.error(
+                    origin,
+                    format!("Repetition count {digits} exceeds {MAX_EXPANSION}"),
+                )
+            })?;
+        *i = end;
+        Ok(Some(count))
+    }
+
+    /// Appends `count` copies of `chars` to `out`, unless that would make it
+    /// longer than `MAX_EXPANSION`
+    fn repeat(&self, chars: &[Char], count: usize, at: Origin, out: &mut Vec<Char>) -> Result<()> {
+        if out.len().saturating_add(chars.len().saturating_mul(count)) > MAX_EXPANSION {
+            return Err(self.error(at, format!("Expansion exceeds {MAX_EXPANSION} characters")));
+        }
+        out.extend(std::iter::repeat_n(chars, count).flatten());
+        Ok(())
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<Vec<Char>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| BrainfcError::io(format!("Could not open {path:?}"), e))?;
+        let file = self.files.len();
+        self.files.push(path.to_path_buf());
+
+        let mut pos = Pos { line: 1, col: 0 };
+        Ok(text
+            .chars()
+            .map(|c| {
+                pos.col += 1;
+                let origin = Origin { file, pos };
+                if c == '\n' {
+                    pos.line += 1;
+                    pos.col = 0;
+                }
+                (c, origin)
+            })
+            .collect())
+    }
+
+    fn expand_file(&mut self, path: &Path, at: Option<Origin>, out: &mut Vec<Char>) -> Result<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(start) = self.includes.iter().position(|(p, _)| *p == canonical) {
+            let cycle: Vec<String> = self.includes[start..]
+                .iter()
+                .map(|(_, p)| p.as_path())
+                .chain([path])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(self.error(at.unwrap(), format!("Include cycle {}", cycle.join(" -> "))));
+        }
+        let text = self.read_file(path)?;
+        self.includes.push((canonical, path.to_path_buf()));
+        self.expand(&text, out)?;
+        self.includes.pop();
+        Ok(())
+    }
+
+    fn expand(&mut self, text: &[Char], out: &mut Vec<Char>) -> Result<()> {
+        let mut i = 0;
+        while i < text.len() {
+            let (c, origin) = text[i];
+            i += 1;
+            if c != '@' {
+                let count = match c.is_whitespace() {
+                    true => None,
+                    false => self.repetition(text, &mut i)?,
+                };
+                self.repeat(&[(c, origin)], count.unwrap_or(1), origin, out)?;
+                continue;
+            }
+
+            let name = ident(text, &mut i);
+            match name.as_str() {
+                "" => out.push((c, origin)),
+                "define" => self.define(text, &mut i, origin)?,
+                "include" => self.include(text, &mut i, origin, out)?,
+                _ => self.call(&name, text, &mut i, origin, out)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// `@define name(params) { body }`, starting after `@define`
+    fn define(&mut self, text: &[Char], i: &mut usize, at: Origin) -> Result<()> {
+        skip_whitespace(text, i);
+        let name = ident(text, i);
+        if name.is_empty() {
+            return Err(self.error(at, "Expected a macro name after @define"));
+        }
+        skip_whitespace(text, i);
+
+        let mut params = Vec::new();
+        if text.get(*i).map(|(c, _)| *c) == Some('(') {
+            let args = self.arguments(text, i, at)?;
+            for param in args {
+                let param: String = trim(&param).iter().map(|(c, _)| c).collect();
+                if param.is_empty() || !param.chars().all(is_ident_char) {
+                    return Err(self.error(at, format!("Invalid parameter `{param}` of @{name}")));
+                }
+                params.push(param);
+            }
+            skip_whitespace(text, i);
+        }
+
+        if text.get(*i).map(|(c, _)| *c) != Some('{') {
+            return Err(self.error(at, format!("Expected `{{` after @define {name}")));
+        }
+        let start = *i + 1;
+        let mut depth = 0;
+        while let Some((c, _)) = text.get(*i) {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            *i += 1;
+            if depth == 0 {
+                let body = trim(&text[start..*i - 1]).to_vec();
+                self.macros.insert(name, Macro { params, body });
+                return Ok(());
+            }
+        }
+        Err(self.error(at, format!("Unterminated body of @{name}")))
+    }
+
+    /// `@include "path"`, starting after `@include`
+    fn include(
+        &mut self,
+        text: &[Char],
+        i: &mut usize,
+        at: Origin,
+        out: &mut Vec<Char>,
+    ) -> Result<()> {
+        skip_whitespace(text, i);
+        if text.get(*i).map(|(c, _)| *c) != Some('"') {
+            return Err(self.error(at, "Expected a quoted path after @include"));
+        }
+        let start = *i + 1;
+        let Some(len) = text[start..].iter().position(|(c, _)| *c == '"') else {
+            return Err(self.error(at, "Unterminated path of @include"));
+        };
+        *i = start + len + 1;
+
+        let path: String = text[start..start + len].iter().map(|(c, _)| c).collect();
+        let dir = self.files[at.file].parent().unwrap_or(Path::new(""));
+        self.expand_file(&dir.join(path), Some(at), out)
+    }
+
+    /// The comma-separated text between the parentheses at `text[*i..]`
+    fn arguments(&self, text: &[Char], i: &mut usize, at: Origin) -> Result<Vec<Vec<Char>>> {
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        while let Some(&(c, origin)) = text.get(*i) {
+            *i += 1;
+            match c {
+                '(' if depth == 0 => {
+                    depth += 1;
+                    continue;
+                }
+                '(' => depth += 1,
+                ')' if depth == 1 => {
+                    if args.len() == 1 && trim(&args[0]).is_empty() {
+                        args.clear();
+                    }
+                    return Ok(args);
+                }
+                ')' => depth -= 1,
+                ',' if depth == 1 => {
+                    args.push(Vec::new());
+                    continue;
+                }
+                _ => {}
+            }
+            args.last_mut().unwrap().push((c, origin));
+        }
+        Err(self.error(at, "Unterminated argument list"))
+    }
+
+    /// `@name(args)*N`, starting after `@name`
+    fn call(
+        &mut self,
+        name: &str,
+        text: &[Char],
+        i: &mut usize,
+        at: Origin,
+        out: &mut Vec<Char>,
+    ) -> Result<()> {
+        let Some(params) = self.macros.get(name).map(|m| m.params.len()) else {
+            return Err(self.error(at, format!("Unknown macro @{name}")));
+        };
+        if self.calls.iter().any(|call| call == name) {
+            return Err(self.error(at, format!("@{name} expands to itself")));
+        }
+        // Macros without parameters leave a following `(` alone, it may be pbrain
+        let args = match params {
+            0 => Vec::new(),
+            _ => self.arguments(text, i, at)?,
+        };
+        if args.len() != params {
+            return Err(self.error(
+                at,
+                format!(
+                    "@{name} takes {params} arguments but {} were given",
+                    args.len()
+                ),
+            ));
+        }
+        let count = self.repetition(text, i)?.unwrap_or(1);
+        let args = args
+            .iter()
+            .map(|arg| {
+                let mut expanded = Vec::new();
+                self.expand(arg, &mut expanded)?;
+                Ok(expanded)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let substituted = self.substitute(&self.macros[name], &args)?;
+        let mut expansion = Vec::new();
+        self.calls.push(name.to_string());
+        self.expand(&substituted, &mut expansion)?;
+        self.calls.pop();
+        self.repeat(&expansion, count, at, out)
+    }
+
+    /// The body of `m` with every `$param` replaced by its argument
+    fn substitute(&self, m: &Macro, args: &[Vec<Char>]) -> Result<Vec<Char>> {
+        let body = &m.body;
+        let mut substituted = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let (c, origin) = body[i];
+            i += 1;
+            if c != '$' {
+                substituted.push((c, origin));
+                continue;
+            }
+            let param = ident(body, &mut i);
+            match m.params.iter().position(|p| *p == param) {
+                Some(n) => substituted.extend_from_slice(trim(&args[n])),
+                None if param.is_empty() => substituted.push((c, origin)),
+                None => return Err(self.error(origin, format!("Unknown parameter ${param}"))),
+            }
+        }
+        Ok(substituted)
+    }
+}
+
+/// Reads `path` and expands its macros and includes
+pub fn expand(path: &Path) -> Result<Expansion> {
+    let mut expander = Expander {
+        files: Vec::new(),
+        macros: HashMap::new(),
+        includes: Vec::new(),
+        calls: Vec::new(),
+    };
+    let mut chars = Vec::new();
+    expander.expand_file(path, None, &mut chars)?;
+
+    let mut source = String::with_capacity(chars.len());
+    let mut lines = vec![Vec::new()];
+    for (c, origin) in chars {
+        source.push(c);
+        lines.last_mut().unwrap().push(origin);
+        if c == '\n' {
+            lines.push(Vec::new());
+        }
+    }
+
+    Ok(Expansion {
+        source,
+        map: SourceMap {
+            files: expander.files,
+            lines,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes `files` to a fresh directory and expands the first one
+    fn expand_files(name: &str, files: &[(&str, &str)]) -> Result<Expansion> {
+        let dir = std::env::temp_dir().join(format!(
+            "brainfc-preprocessor-{name}-{}",
+            std::process::id()
+        ));
+        for (path, text) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        let expansion = expand(&dir.join(files[0].0));
+        std::fs::remove_dir_all(&dir).unwrap();
+        expansion
+    }
+
+    fn expand_text(name: &str, text: &str) -> Result<String> {
+        expand_files(name, &[("main.bf", text)]).map(|expansion| expansion.source)
+    }
+
+    #[test]
+    fn macros_and_repetitions() {
+        let source = "@define clear { [-] }\n+*3 @clear*2 >*2 @ *";
+        assert_eq!(
+            expand_text("macros", source).unwrap(),
+            "\n+++ [-][-] >> @ *"
+        );
+    }
+
+    #[test]
+    fn parameters() {
+        let source = "@define add(n, c) { >+*$n<$c }@define one { 1 }@add(3, .) @add(@one, ( ))";
+        assert_eq!(expand_text("params", source).unwrap(), ">+++<. >+<( )");
+
+        let err = expand_text("arity", "@define add(n, c) { $n$c }@add(1)").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("@add takes 2 arguments but 1 were given"));
+        let err = expand_text("unknown", "@define add(n) { $m }@add(1)").unwrap_err();
+        assert!(err.to_string().contains("Unknown parameter $m"));
+    }
+
+    #[test]
+    fn includes() {
+        let files = [
+            ("main.bf", "+@include \"lib/print.bf\"-"),
+            ("lib/print.bf", "@include \"dot.bf\"@dot"),
+            ("lib/dot.bf", "@define dot { . }"),
+        ];
+        assert_eq!(expand_files("includes", &files).unwrap().source, "+.-");
+
+        let files = [
+            ("main.bf", "@include \"a.bf\""),
+            ("a.bf", "+\n @include \"main.bf\""),
+        ];
+        let Err(err) = expand_files("cycle", &files) else {
+            panic!("cycle was expanded");
+        };
+        let err = err.to_string();
+        assert!(err.contains("Include cycle "), "{err}");
+        assert!(err.ends_with("a.bf:2:2"), "{err}");
+    }
+
+    #[test]
+    fn recursion_and_size_limits() {
+        let source = "@define a { @b }@define b { +@a }@a";
+        let err = expand_text("recursion", source).unwrap_err();
+        assert!(err.to_string().contains("@a expands to itself"), "{err}");
+
+        let err = expand_text("count", "+*99999999999999999999").unwrap_err();
+        assert!(err.to_string().contains("Repetition count"), "{err}");
+        let source = "@define a { +*4096 }@define b { @a*4096 }@b";
+        let err = expand_text("size", source).unwrap_err();
+        assert!(err.to_string().contains("Expansion exceeds"), "{err}");
+        assert_eq!(
+            expand_text("fits", "+*4194304").unwrap().len(),
+            MAX_EXPANSION
+        );
+    }
+
+    #[test]
+    fn source_map() {
+        let files = [
+            (
+                "main.bf",
+                "@define open { >[ }\n+\n@include \"lib.bf\"\n@open",
+            ),
+            ("lib.bf", ">>\n+]"),
+        ];
+        let expansion = expand_files("map", &files).unwrap();
+        assert_eq!(expansion.source, "\n+\n>>\n+]\n>[");
+
+        let location = expansion.map.locate(Pos { line: 3, col: 2 }).unwrap();
+        assert!(location.file.unwrap().ends_with("lib.bf"));
+        assert_eq!(location.pos, Pos { line: 1, col: 2 });
+        assert_eq!(expansion.map.locate(Pos { line: 9, col: 1 }), None);
+
+        let tokens = crate::lexer::parse(&expansion.source);
+        let err = crate::parser::parse(tokens).unwrap_err();
+        let err = expansion.map.map_error(err).to_string();
+        assert!(
+            err.starts_with("parse error: Unmatched closing bracket at "),
+            "{err}"
+        );
+        assert!(err.ends_with("lib.bf:2:2"), "{err}");
+
+        // Only the locations of an error are mapped, never its message
+        let err = BrainfcError::Parse("Expected 2:1".to_string());
+        assert_eq!(
+            expansion.map.map_error(err).to_string(),
+            "parse error: Expected 2:1"
+        );
+    }
+}
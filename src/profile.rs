@@ -0,0 +1,208 @@
+//! Support for `--profile` builds. Every block and every loop of the program
+//! is a site with its own execution counter. When the program finishes,
+//! `bf_profile_report` prints all counters with the source position of their
+//! site to stderr, followed by the loops that ran the most iterations.
+
+use crate::lexer::Pos;
+use crate::parser::{Block, Program};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SiteKind {
+    /// Counts how often a block ran
+    Block = 0,
+    /// Counts the iterations of a loop
+    Loop = 1,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Site {
+    pub pos: Pos,
+    pub kind: SiteKind,
+    /// Operations one count of the site stands for: the tokens of a block,
+    /// one for a reset, a multiplication or the test of a loop iteration
+    pub ops: u64,
+}
+
+impl Site {
+    /// The `{ line, col, kind }` record `bf_profile_report` reads
+    pub fn record(&self) -> [u32; 3] {
+        [self.pos.line, self.pos.col, self.kind as u32]
+    }
+}
+
+/// The sites of `program` in the order backends instrument them: a loop
+/// comes before the blocks of its body. A procedure is a block counting its
+/// definitions, followed by the blocks of its body.
+pub fn sites(program: &Program) -> Vec<Site> {
+    let mut sites = Vec::new();
+    collect_sites(program, &mut sites);
+    sites
+}
+
+fn collect_sites(program: &Program, sites: &mut Vec<Site>) {
+    for block in program {
+        let (pos, ops) = match block {
+            Block::Simple(tokens) => (
+                tokens.first().map(|token| token.pos).unwrap_or_default(),
+                tokens.len() as u64,
+            ),
+            Block::Loop(body, span) => {
+                sites.push(Site {
+                    pos: span.start,
+                    kind: SiteKind::Loop,
+                    ops: 1,
+                });
+                collect_sites(body, sites);
+                continue;
+            }
+            Block::Procedure(body, span) => {
+                sites.push(Site {
+                    pos: span.start,
+                    kind: SiteKind::Block,
+                    ops: 1,
+                });
+                collect_sites(body, sites);
+                continue;
+            }
+            Block::Reset { span, .. } | Block::Multiply { span, .. } => (span.start, 1),
+            Block::JmpLoop { .. } => (Pos::default(), 1),
+        };
+        sites.push(Site {
+            pos,
+            kind: SiteKind::Block,
+            ops,
+        });
+    }
+}
+
+/// The counters of a report `bf_profile_report` printed to `stderr`, in the
+/// order of `sites`
+pub fn parse_report(stderr: &str) -> Vec<u64> {
+    stderr
+        .lines()
+        .skip_while(|line| *line != "profile:")
+        .skip(2)
+        .take_while(|line| *line != "hot loops:")
+        .filter_map(|line| line.split_whitespace().last()?.parse().ok())
+        .collect()
+}
+
+/// Operations the program executed according to the `counts` of its `sites`
+pub fn executed_ops(sites: &[Site], counts: &[u64]) -> u64 {
+    sites
+        .iter()
+        .zip(counts)
+        .map(|(site, count)| site.ops * count)
+        .sum()
+}
+
+/// `void bf_profile_report(const uint32_t *sites, uint64_t *counts, size_t n)`,
+/// prints through `dprintf`. The summary lists up to ten loops, whose counters
+/// are cleared as they are listed.
+pub const RUNTIME_ASM: &str = r#"
+    .section .rodata
+.Lprof_header:
+    .asciz "profile:\n   line:col  site          count\n"
+.Lprof_row:
+    .asciz "%7u:%-4u %-5s %14lu\n"
+.Lprof_block:
+    .asciz "block"
+.Lprof_loop:
+    .asciz "loop"
+.Lprof_hot_header:
+    .asciz "hot loops:\n"
+.Lprof_hot_row:
+    .asciz "%7u:%-4u %14lu iterations\n"
+
+    .text
+    .type bf_profile_report, @function
+bf_profile_report:
+    push %rbx
+    push %rbp
+    push %r12
+    push %r13
+    push %r14
+    push %r15
+    sub $8, %rsp
+    mov %rdi, %r12
+    mov %rsi, %r13
+    mov %rdx, %r14
+
+    mov $2, %edi
+    lea .Lprof_header(%rip), %rsi
+    xor %eax, %eax
+    call dprintf@PLT
+
+    xor %ebx, %ebx
+.Lprof_rows:
+    cmp %r14, %rbx
+    jae .Lprof_hot
+    lea (%rbx,%rbx,2), %rax
+    lea (%r12,%rax,4), %r15
+    mov $2, %edi
+    lea .Lprof_row(%rip), %rsi
+    mov (%r15), %edx
+    mov 4(%r15), %ecx
+    lea .Lprof_block(%rip), %r8
+    lea .Lprof_loop(%rip), %rax
+    cmpl $0, 8(%r15)
+    cmovne %rax, %r8
+    mov (%r13,%rbx,8), %r9
+    xor %eax, %eax
+    call dprintf@PLT
+    inc %rbx
+    jmp .Lprof_rows
+
+.Lprof_hot:
+    mov $2, %edi
+    lea .Lprof_hot_header(%rip), %rsi
+    xor %eax, %eax
+    call dprintf@PLT
+    mov $10, %ebp
+
+.Lprof_hot_next:
+    test %ebp, %ebp
+    jz .Lprof_done
+    xor %ebx, %ebx
+    xor %r15d, %r15d
+    xor %r10d, %r10d
+.Lprof_find:
+    cmp %r14, %rbx
+    jae .Lprof_found
+    lea (%rbx,%rbx,2), %rax
+    cmpl $0, 8(%r12,%rax,4)
+    je .Lprof_skip
+    mov (%r13,%rbx,8), %rax
+    cmp %r10, %rax
+    jbe .Lprof_skip
+    mov %rax, %r10
+    mov %rbx, %r15
+.Lprof_skip:
+    inc %rbx
+    jmp .Lprof_find
+.Lprof_found:
+    test %r10, %r10
+    jz .Lprof_done
+    movq $0, (%r13,%r15,8)
+    lea (%r15,%r15,2), %rax
+    mov $2, %edi
+    lea .Lprof_hot_row(%rip), %rsi
+    mov (%r12,%rax,4), %edx
+    mov 4(%r12,%rax,4), %ecx
+    mov %r10, %r8
+    xor %eax, %eax
+    call dprintf@PLT
+    dec %ebp
+    jmp .Lprof_hot_next
+
+.Lprof_done:
+    add $8, %rsp
+    pop %r15
+    pop %r14
+    pop %r13
+    pop %r12
+    pop %rbp
+    pop %rbx
+    ret
+    .size bf_profile_report, .-bf_profile_report
+"#;
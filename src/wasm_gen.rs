@@ -0,0 +1,362 @@
+mod instr;
+mod module;
+
+use instr::Instr;
+use module::{FuncType, Function, Import, WasmModule};
+
+use std::path::PathBuf;
+
+use crate::error::{BrainfcError, Result};
+use crate::lexer::Op;
+use crate::options::{CompileOptions, CrateType, ExitCode, WasmAbi};
+use crate::parser::{Block, Program};
+
+// Scratch space for WASI iovecs lives below the tape
+const IOVEC: i32 = 0;
+const NBYTES: i32 = 8;
+const IO_BYTE: i32 = 12;
+
+const TAPE_START: i32 = 16;
+const TAPE_SIZE: i32 = 30_000;
+const PAGE_SIZE: i32 = 65_536;
+
+// The only local of the entry function: absolute address of the current cell
+const PTR: u32 = 0;
+
+const READ_TYPE: FuncType = FuncType {
+    params: 0,
+    results: 1,
+};
+const WRITE_TYPE: FuncType = FuncType {
+    params: 1,
+    results: 0,
+};
+const ENTRY_TYPE: FuncType = FuncType {
+    params: 0,
+    results: 0,
+};
+const WASI_IO_TYPE: FuncType = FuncType {
+    params: 4,
+    results: 1,
+};
+
+struct WasmBuilder {
+    code: Vec<Instr>,
+    read_fn: u32,
+    write_fn: u32,
+}
+
+impl WasmBuilder {
+    fn new(read_fn: u32, write_fn: u32) -> Self {
+        WasmBuilder {
+            code: vec![Instr::I32Const(TAPE_START), Instr::LocalSet(PTR)],
+            read_fn,
+            write_fn,
+        }
+    }
+
+    fn emit(&mut self, instrs: &[Instr]) {
+        self.code.extend_from_slice(instrs);
+    }
+
+    fn cell_addr(&mut self, diff: i32) {
+        self.emit(&[Instr::LocalGet(PTR)]);
+        if diff != 0 {
+            self.emit(&[Instr::I32Const(diff), Instr::I32Add]);
+        }
+    }
+
+    /// Traps unless the cell `diff` away is on the tape, so that the program
+    /// cannot overwrite the scratch space below it. This runs after every
+    /// move, including the moves of hot loops, at the cost of a compare and
+    /// a branch each time.
+    fn check_bounds(&mut self, diff: i32) {
+        use Instr::*;
+        self.cell_addr(diff);
+        self.emit(&[I32Const(TAPE_START), I32Sub, I32Const(TAPE_SIZE), I32GeU]);
+        self.emit(&[If, Unreachable, End]);
+    }
+
+    fn compile(&mut self, program: &Program) {
+        for block in program {
+            self.compile_block(block);
+        }
+    }
+
+    fn compile_instruction(&mut self, op: &Op) {
+        use Instr::*;
+        match &op {
+            Op::Move(x) => {
+                self.emit(&[LocalGet(PTR), I32Const(*x as i32), I32Add, LocalSet(PTR)]);
+                self.check_bounds(0);
+            }
+            Op::Modify(x) => {
+                self.emit(&[LocalGet(PTR), LocalGet(PTR), I32Load8U]);
+                self.emit(&[I32Const(*x as i32), I32Add, I32Store8]);
+            }
+            Op::Inp(x) => {
+                for _ in 0..(*x) {
+                    self.emit(&[LocalGet(PTR), Call(self.read_fn), I32Store8]);
+                }
+            }
+            Op::Outp(x) => {
+                for _ in 0..(*x) {
+                    self.emit(&[LocalGet(PTR), I32Load8U, Call(self.write_fn)]);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn compile_block(&mut self, block: &Block) {
+        use Block::*;
+        match &block {
+            Simple(ref instrs) => instrs.iter().for_each(|i| self.compile_instruction(&i.op)),
+            Loop(ref program, _) => self.compile_loop(program),
+            Reset { .. } => {
+                self.emit(&[Instr::LocalGet(PTR), Instr::I32Const(0), Instr::I32Store8])
+            }
+            Multiply { ops, .. } => self.compile_multiply(ops),
+            _ => {}
+        }
+    }
+
+    fn compile_loop(&mut self, program: &Program) {
+        use Instr::*;
+        self.emit(&[Block, Loop, LocalGet(PTR), I32Load8U, I32Eqz, BrIf(1)]);
+        self.compile(program);
+        self.emit(&[Br(0), End, End]);
+    }
+
+    /// Like the loop it replaces, touches no other cell if the counter is zero
+    fn compile_multiply(&mut self, ops: &[(i32, i32)]) {
+        use Instr::*;
+        self.emit(&[LocalGet(PTR), I32Load8U, If]);
+        for (diff, multiply_val) in ops {
+            self.check_bounds(*diff);
+            self.cell_addr(*diff);
+            self.cell_addr(*diff);
+            self.emit(&[I32Load8U, LocalGet(PTR), I32Load8U]);
+            self.emit(&[I32Const(*multiply_val), I32Mul, I32Add, I32Store8]);
+        }
+        self.emit(&[LocalGet(PTR), I32Const(0), I32Store8, End]);
+    }
+}
+
+/// `read` helper for WASI: reads one byte from stdin, returns -1 on EOF
+fn wasi_read(fd_read: u32) -> Function {
+    use Instr::*;
+    Function {
+        name: "read",
+        func_type: READ_TYPE,
+        locals: 0,
+        export: None,
+        body: vec![
+            I32Const(IOVEC),
+            I32Const(IO_BYTE),
+            I32Store,
+            I32Const(IOVEC + 4),
+            I32Const(1),
+            I32Store,
+            I32Const(0),
+            I32Const(IOVEC),
+            I32Const(1),
+            I32Const(NBYTES),
+            Call(fd_read),
+            Drop,
+            I32Const(NBYTES),
+            I32Load,
+            I32Eqz,
+            IfI32,
+            I32Const(-1),
+            Else,
+            I32Const(IO_BYTE),
+            I32Load8U,
+            End,
+        ],
+    }
+}
+
+/// `write` helper for WASI: writes the low byte of its argument to stdout
+fn wasi_write(fd_write: u32) -> Function {
+    use Instr::*;
+    Function {
+        name: "write",
+        func_type: WRITE_TYPE,
+        locals: 0,
+        export: None,
+        body: vec![
+            I32Const(IO_BYTE),
+            LocalGet(0),
+            I32Store8,
+            I32Const(IOVEC),
+            I32Const(IO_BYTE),
+            I32Store,
+            I32Const(IOVEC + 4),
+            I32Const(1),
+            I32Store,
+            I32Const(1),
+            I32Const(IOVEC),
+            I32Const(1),
+            I32Const(NBYTES),
+            Call(fd_write),
+            Drop,
+        ],
+    }
+}
+
+fn build_module(program: &Program, abi: WasmAbi) -> WasmModule {
+    let memory_pages = ((TAPE_START + TAPE_SIZE) + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    match abi {
+        WasmAbi::Env => {
+            let mut builder = WasmBuilder::new(0, 1);
+            builder.compile(program);
+
+            WasmModule {
+                imports: vec![
+                    Import {
+                        module: "env",
+                        name: "read",
+                        func_type: READ_TYPE,
+                    },
+                    Import {
+                        module: "env",
+                        name: "write",
+                        func_type: WRITE_TYPE,
+                    },
+                ],
+                functions: vec![Function {
+                    name: "run",
+                    func_type: ENTRY_TYPE,
+                    locals: 1,
+                    export: Some("run"),
+                    body: builder.code,
+                }],
+                memory_pages: memory_pages as u32,
+            }
+        }
+        WasmAbi::Wasi => {
+            let mut builder = WasmBuilder::new(2, 3);
+            builder.compile(program);
+
+            WasmModule {
+                imports: vec![
+                    Import {
+                        module: "wasi_snapshot_preview1",
+                        name: "fd_read",
+                        func_type: WASI_IO_TYPE,
+                    },
+                    Import {
+                        module: "wasi_snapshot_preview1",
+                        name: "fd_write",
+                        func_type: WASI_IO_TYPE,
+                    },
+                ],
+                functions: vec![
+                    wasi_read(0),
+                    wasi_write(1),
+                    Function {
+                        name: "start",
+                        func_type: ENTRY_TYPE,
+                        locals: 1,
+                        export: Some("_start"),
+                        body: builder.code,
+                    },
+                ],
+                memory_pages: memory_pages as u32,
+            }
+        }
+    }
+}
+
+pub fn compile(program: Program, options: &CompileOptions) -> Result<PathBuf> {
+    if options.freestanding {
+        return Err(BrainfcError::Codegen(
+            "--freestanding only applies to native backends".to_string(),
+        ));
+    }
+    if options.crate_type == CrateType::Cdylib {
+        return Err(BrainfcError::Codegen(
+            "--crate-type cdylib only applies to native backends".to_string(),
+        ));
+    }
+    if options.debug_source.is_some() {
+        return Err(BrainfcError::Codegen(
+            "-g only applies to native backends".to_string(),
+        ));
+    }
+    if options.embedded_input.is_some() {
+        return Err(BrainfcError::Codegen(
+            "inline input only applies to native backends".to_string(),
+        ));
+    }
+    if options.exit_code != ExitCode::Zero || options.dump_tape_at_exit || options.profile {
+        return Err(BrainfcError::Codegen(
+            "--exit-code, --dump-tape-at-exit and --profile only apply to native backends"
+                .to_string(),
+        ));
+    }
+
+    let module = build_module(&program, options.wasm_abi);
+
+    if options.show_ir {
+        eprint!("{}", module.to_wat());
+    }
+
+    let (output, contents) = match options.output.extension() {
+        Some(ext) if ext == "wat" => (options.output.clone(), module.to_wat().into_bytes()),
+        _ => (options.output.with_extension("wasm"), module.to_wasm()),
+    };
+
+    std::fs::write(&output, contents)
+        .map_err(|e| BrainfcError::io(format!("Could not write {output:?}"), e))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::optimizer::Optimizer;
+
+    /// Runs `source` as a module of `WasmAbi::Env` without input and returns
+    /// what it printed, or `None` if it trapped
+    fn run(source: &str) -> Option<Vec<u8>> {
+        let source = source.to_string();
+        let program = crate::parser::parse(crate::lexer::parse(&source)).unwrap();
+        let wasm = build_module(&Optimizer::new().optimize(program), WasmAbi::Env).to_wasm();
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &wasm[..]).unwrap();
+        let mut store = wasmi::Store::new(&engine, Vec::new());
+        let mut linker = wasmi::Linker::new(&engine);
+        linker
+            .func_wrap("env", "read", || -1i32)
+            .unwrap()
+            .func_wrap(
+                "env",
+                "write",
+                |mut caller: wasmi::Caller<'_, Vec<u8>>, byte: i32| {
+                    caller.data_mut().push(byte as u8)
+                },
+            )
+            .unwrap();
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let run = instance.get_typed_func::<(), ()>(&store, "run").unwrap();
+        run.call(&mut store, ()).ok()?;
+        Some(store.into_data())
+    }
+
+    #[test]
+    fn wasm_traps_off_the_tape() {
+        assert_eq!(run("<+"), None);
+        assert_eq!(run(&">".repeat(30_000)), None);
+        assert_eq!(run("+[<+>-]"), None);
+        assert_eq!(run(">[<+>-]+."), Some(vec![1]));
+        assert_eq!(run(&(">".repeat(29_999) + "+.")), Some(vec![1]));
+    }
+}
@@ -0,0 +1,144 @@
+/// The subset of WebAssembly instructions needed to express a brainfuck program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Block,
+    Loop,
+    If,
+    IfI32,
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Call(u32),
+    Drop,
+    Unreachable,
+    LocalGet(u32),
+    LocalSet(u32),
+    I32Const(i32),
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32Eqz,
+    I32GeU,
+    I32Load,
+    I32Load8U,
+    I32Store,
+    I32Store8,
+}
+
+impl Instr {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        use Instr::*;
+        match self {
+            Block => out.extend([0x02, 0x40]),
+            Loop => out.extend([0x03, 0x40]),
+            If => out.extend([0x04, 0x40]),
+            IfI32 => out.extend([0x04, 0x7f]),
+            Else => out.push(0x05),
+            End => out.push(0x0b),
+            Br(depth) => {
+                out.push(0x0c);
+                write_unsigned(out, *depth as u64);
+            }
+            BrIf(depth) => {
+                out.push(0x0d);
+                write_unsigned(out, *depth as u64);
+            }
+            Call(idx) => {
+                out.push(0x10);
+                write_unsigned(out, *idx as u64);
+            }
+            Drop => out.push(0x1a),
+            Unreachable => out.push(0x00),
+            LocalGet(idx) => {
+                out.push(0x20);
+                write_unsigned(out, *idx as u64);
+            }
+            LocalSet(idx) => {
+                out.push(0x21);
+                write_unsigned(out, *idx as u64);
+            }
+            I32Const(val) => {
+                out.push(0x41);
+                write_signed(out, *val as i64);
+            }
+            I32Add => out.push(0x6a),
+            I32Sub => out.push(0x6b),
+            I32Mul => out.push(0x6c),
+            I32Eqz => out.push(0x45),
+            I32GeU => out.push(0x4f),
+            // memarg is (alignment exponent, offset)
+            I32Load => out.extend([0x28, 0x02, 0x00]),
+            I32Load8U => out.extend([0x2d, 0x00, 0x00]),
+            I32Store => out.extend([0x36, 0x02, 0x00]),
+            I32Store8 => out.extend([0x3a, 0x00, 0x00]),
+        }
+    }
+
+    pub fn to_wat(self, fn_name: impl Fn(u32) -> String) -> String {
+        use Instr::*;
+        match self {
+            Block => "block".to_string(),
+            Loop => "loop".to_string(),
+            If => "if".to_string(),
+            IfI32 => "if (result i32)".to_string(),
+            Else => "else".to_string(),
+            End => "end".to_string(),
+            Br(depth) => format!("br {depth}"),
+            BrIf(depth) => format!("br_if {depth}"),
+            Call(idx) => format!("call {}", fn_name(idx)),
+            Drop => "drop".to_string(),
+            Unreachable => "unreachable".to_string(),
+            LocalGet(idx) => format!("local.get {idx}"),
+            LocalSet(idx) => format!("local.set {idx}"),
+            I32Const(val) => format!("i32.const {val}"),
+            I32Add => "i32.add".to_string(),
+            I32Sub => "i32.sub".to_string(),
+            I32Mul => "i32.mul".to_string(),
+            I32Eqz => "i32.eqz".to_string(),
+            I32GeU => "i32.ge_u".to_string(),
+            I32Load => "i32.load".to_string(),
+            I32Load8U => "i32.load8_u".to_string(),
+            I32Store => "i32.store".to_string(),
+            I32Store8 => "i32.store8".to_string(),
+        }
+    }
+
+    pub fn opens_block(&self) -> bool {
+        matches!(self, Instr::Block | Instr::Loop | Instr::If | Instr::IfI32)
+    }
+
+    pub fn closes_block(&self) -> bool {
+        matches!(self, Instr::End)
+    }
+}
+
+pub fn write_unsigned(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_signed(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        let sign_bit_clear = byte & 0x40 == 0;
+        if (val == 0 && sign_bit_clear) || (val == -1 && !sign_bit_clear) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_unsigned(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
@@ -0,0 +1,202 @@
+use super::instr::{write_name, write_unsigned, Instr};
+
+/// Function signature where every parameter and result is an `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: u32,
+    pub results: u32,
+}
+
+pub struct Import {
+    pub module: &'static str,
+    pub name: &'static str,
+    pub func_type: FuncType,
+}
+
+/// A locally defined function. `body` does not include the final `end`.
+pub struct Function {
+    pub name: &'static str,
+    pub func_type: FuncType,
+    pub locals: u32,
+    pub export: Option<&'static str>,
+    pub body: Vec<Instr>,
+}
+
+/// Minimal single-memory WebAssembly module. Function indices follow the
+/// spec: imports come first, then locally defined functions.
+pub struct WasmModule {
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    pub memory_pages: u32,
+}
+
+impl WasmModule {
+    fn types(&self) -> Vec<FuncType> {
+        let mut types = Vec::new();
+        let all = self
+            .imports
+            .iter()
+            .map(|i| i.func_type)
+            .chain(self.functions.iter().map(|f| f.func_type));
+        for func_type in all {
+            if !types.contains(&func_type) {
+                types.push(func_type);
+            }
+        }
+        types
+    }
+
+    fn type_idx(&self, func_type: FuncType) -> u32 {
+        self.types().iter().position(|t| *t == func_type).unwrap() as u32
+    }
+
+    fn fn_name(&self, idx: u32) -> String {
+        let idx = idx as usize;
+        if idx < self.imports.len() {
+            return format!("${}", self.imports[idx].name);
+        }
+        format!("${}", self.functions[idx - self.imports.len()].name)
+    }
+
+    fn wat_signature(func_type: FuncType) -> String {
+        let mut sig = String::new();
+        for _ in 0..func_type.params {
+            sig.push_str(" (param i32)");
+        }
+        for _ in 0..func_type.results {
+            sig.push_str(" (result i32)");
+        }
+        sig
+    }
+
+    pub fn to_wat(&self) -> String {
+        let mut wat = String::from("(module\n");
+
+        for import in &self.imports {
+            wat.push_str(&format!(
+                "  (import \"{}\" \"{}\" (func ${}{}))\n",
+                import.module,
+                import.name,
+                import.name,
+                Self::wat_signature(import.func_type)
+            ));
+        }
+
+        wat.push_str(&format!(
+            "  (memory (export \"memory\") {})\n",
+            self.memory_pages
+        ));
+
+        for function in &self.functions {
+            wat.push_str(&format!("  (func ${}", function.name));
+            if let Some(export) = function.export {
+                wat.push_str(&format!(" (export \"{export}\")"));
+            }
+            wat.push_str(&Self::wat_signature(function.func_type));
+            for _ in 0..function.locals {
+                wat.push_str(" (local i32)");
+            }
+            wat.push('\n');
+
+            let mut depth = 2;
+            for instr in &function.body {
+                if instr.closes_block() || *instr == Instr::Else {
+                    depth -= 1;
+                }
+                wat.push_str(&"  ".repeat(depth));
+                wat.push_str(&instr.to_wat(|idx| self.fn_name(idx)));
+                wat.push('\n');
+                if instr.opens_block() || *instr == Instr::Else {
+                    depth += 1;
+                }
+            }
+            wat.push_str("  )\n");
+        }
+
+        wat.push_str(")\n");
+        wat
+    }
+
+    pub fn to_wasm(&self) -> Vec<u8> {
+        let mut out = b"\0asm".to_vec();
+        out.extend(1u32.to_le_bytes());
+
+        let types = self.types();
+        write_section(&mut out, 1, types.len(), |sec| {
+            for t in &types {
+                sec.push(0x60);
+                write_unsigned(sec, t.params as u64);
+                sec.extend(std::iter::repeat_n(0x7f, t.params as usize));
+                write_unsigned(sec, t.results as u64);
+                sec.extend(std::iter::repeat_n(0x7f, t.results as usize));
+            }
+        });
+
+        write_section(&mut out, 2, self.imports.len(), |sec| {
+            for import in &self.imports {
+                write_name(sec, import.module);
+                write_name(sec, import.name);
+                sec.push(0x00);
+                write_unsigned(sec, self.type_idx(import.func_type) as u64);
+            }
+        });
+
+        write_section(&mut out, 3, self.functions.len(), |sec| {
+            for function in &self.functions {
+                write_unsigned(sec, self.type_idx(function.func_type) as u64);
+            }
+        });
+
+        write_section(&mut out, 5, 1, |sec| {
+            sec.push(0x00);
+            write_unsigned(sec, self.memory_pages as u64);
+        });
+
+        let exports: Vec<(usize, &'static str)> = self
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.export.map(|name| (i + self.imports.len(), name)))
+            .collect();
+        write_section(&mut out, 7, exports.len() + 1, |sec| {
+            write_name(sec, "memory");
+            sec.extend([0x02, 0x00]);
+            for (idx, name) in &exports {
+                write_name(sec, name);
+                sec.push(0x00);
+                write_unsigned(sec, *idx as u64);
+            }
+        });
+
+        write_section(&mut out, 10, self.functions.len(), |sec| {
+            for function in &self.functions {
+                let mut body = Vec::new();
+                if function.locals > 0 {
+                    write_unsigned(&mut body, 1);
+                    write_unsigned(&mut body, function.locals as u64);
+                    body.push(0x7f);
+                } else {
+                    write_unsigned(&mut body, 0);
+                }
+                for instr in &function.body {
+                    instr.encode(&mut body);
+                }
+                Instr::End.encode(&mut body);
+                write_unsigned(sec, body.len() as u64);
+                sec.extend(body);
+            }
+        });
+
+        out
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, entries: usize, f: impl FnOnce(&mut Vec<u8>)) {
+    let mut section = Vec::new();
+    write_unsigned(&mut section, entries as u64);
+    f(&mut section);
+
+    out.push(id);
+    write_unsigned(out, section.len() as u64);
+    out.extend(section);
+}
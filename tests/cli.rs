@@ -0,0 +1,128 @@
+//! The `brainfc` executable itself, run the way users do
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn brainfc(args: &[&OsStr], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "brainfc {args:?} exited with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Runs the brainfuck program printed by `brainfc gen-print`
+fn printed(output: Output) -> Vec<u8> {
+    let program = String::from_utf8(output.stdout).unwrap();
+    let parsed = brainfc::parse(brainfc::lex(&program)).unwrap();
+    brainfc::interpreter::run(&parsed, &[]).unwrap()
+}
+
+#[test]
+fn gen_print_takes_bytes() {
+    let text = OsStr::from_bytes(b"caf\xe9 \xff");
+    let output = brainfc(&["gen-print".as_ref(), "-n".as_ref(), text], b"");
+    assert_eq!(printed(output), b"caf\xe9 \xff\n");
+
+    let bytes = [0, 1, 255, 0, b'\n'];
+    let output = brainfc(&["gen-print", "--file", "-"].map(OsStr::new), &bytes);
+    assert_eq!(printed(output), bytes);
+}
+
+#[test]
+fn golden_fixtures() {
+    let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let (golden, mandelbrot) = (tests.join("golden"), tests.join("mandelbrot.input"));
+    let args = [
+        "test".as_ref(),
+        golden.as_os_str(),
+        mandelbrot.as_os_str(),
+        "--ext".as_ref(),
+        "inline-input".as_ref(),
+    ];
+    let stdout = String::from_utf8(brainfc(&args, b"").stdout).unwrap();
+    assert!(stdout.contains("3 passed, 0 failed, 0 skipped"), "{stdout}");
+
+    // Without the extension `inline.b` reads stdin instead
+    let output = Command::new(env!("CARGO_BIN_EXE_brainfc"))
+        .arg("test")
+        .arg(&golden)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(8));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("1 passed, 1 failed, 0 skipped"), "{stdout}");
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn debug_info_maps_to_lines_and_loop_blocks() {
+    let dir = std::env::temp_dir().join(format!("brainfc-cli-debug-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("nested.b");
+    std::fs::write(&source, "++\n[>+\n  [>.<-]<-]\n").unwrap();
+    let output = dir.join("nested");
+    let args = [
+        source.as_os_str(),
+        "-g".as_ref(),
+        "--show-llvm-ir".as_ref(),
+        "-o".as_ref(),
+        output.as_os_str(),
+    ];
+    let ir = String::from_utf8(brainfc(&args, b"").stderr).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The inner loop, which the optimizer keeps because it prints, opens
+    // at line 3, column 3 inside the outer one
+    assert!(ir.contains("!DILocation(line: 3, column: 3"), "{ir}");
+    assert_eq!(ir.matches("DILexicalBlock(").count(), 2, "{ir}");
+}
+
+#[test]
+fn bench_prints_json() {
+    let dir = std::env::temp_dir().join(format!("brainfc-cli-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("tiny.b");
+    std::fs::write(&source, "+++[>++<-]>.").unwrap();
+    let args = [
+        "bench".as_ref(),
+        source.as_os_str(),
+        "--json".as_ref(),
+        "--backend".as_ref(),
+        "asm".as_ref(),
+        "--runs".as_ref(),
+        "2".as_ref(),
+        "--warmup".as_ref(),
+        "0".as_ref(),
+    ];
+    let output = brainfc(&args, b"");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let measurements = json.as_array().unwrap();
+    // With and without the optimizer
+    assert_eq!(measurements.len(), 2, "{json}");
+    for (measurement, optimizer) in measurements.iter().zip([true, false]) {
+        assert_eq!(measurement["program"], source.display().to_string());
+        assert_eq!(measurement["backend"], "asm");
+        assert_eq!(measurement["opt_level"], serde_json::Value::Null);
+        assert_eq!(measurement["optimizer"], optimizer);
+        assert_eq!(measurement["runs"], 2);
+        assert!(measurement["mean_ms"].as_f64().unwrap() > 0.0);
+        assert!(measurement["instructions"].as_u64().unwrap() > 0);
+        assert!(measurement["binary_size"].as_u64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,2 @@
+Copies the input until EOF (which reads as 255)
+,+[-.,+]
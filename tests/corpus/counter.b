@@ -0,0 +1,3 @@
+Prints the digits 9 to 0 from a counter and resets cells in loops
++++++++[>++++++++<-]>+<++++++++++[>.-<-]
+>[-]++++++++++.
@@ -0,0 +1,4 @@
+Multiplication loops with positive and negative offsets and nesting
+>>++++[-<+++<++>>]<.<.
+>>>+++[>+++[>++<-]<-]>>.
+[-]<[-]<+++++[>>+++++<<-]>>[<+>>+<-]<.>.
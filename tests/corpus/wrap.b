@@ -0,0 +1,3 @@
+Cells wrap around in both directions
+-.+.--------[-->+<]>.
+>+[+]+++++++++++++++++++++++++++++++++++++++++++++++.
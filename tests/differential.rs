@@ -0,0 +1,510 @@
+//! Runs every program of `tests/corpus` on every execution path and checks
+//! that each prints exactly the bytes of its `.out` file. A program `name.b`
+//! reads `name.in` if it exists and no input otherwise. WebAssembly modules
+//! run in-process on wasmi. Programs of other dialects are checked against
+//! what an equivalent brainfuck program prints.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use brainfc::optimizer::{Optimizer, Pass};
+use brainfc::parser::Program;
+use brainfc::{Backend, CompileOptions, Dialect, Extension};
+
+struct Case {
+    name: String,
+    dialect: Dialect,
+    source: String,
+    input: Vec<u8>,
+    expected: Vec<u8>,
+}
+
+impl Case {
+    fn parse(&self) -> Program {
+        brainfc::parse(brainfc::lex_with(&self.source, &self.dialect, &[])).unwrap()
+    }
+
+    fn check(&self, path: &str, output: &[u8]) {
+        assert!(
+            output == self.expected,
+            "{} ({path}) printed {:?}, expected {:?}",
+            self.name,
+            String::from_utf8_lossy(output),
+            String::from_utf8_lossy(&self.expected)
+        );
+    }
+}
+
+fn corpus() -> Vec<Case> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("b".as_ref()))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs in {dir:?}");
+
+    programs
+        .into_iter()
+        .map(|path| Case {
+            name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+            dialect: Dialect::Brainfuck,
+            source: std::fs::read_to_string(&path).unwrap(),
+            input: std::fs::read(path.with_extension("in")).unwrap_or_default(),
+            expected: std::fs::read(path.with_extension("out")).unwrap(),
+        })
+        .collect()
+}
+
+/// Every subset of `Pass::ALL`, in their usual order
+fn pass_combinations() -> Vec<Vec<Pass>> {
+    (0..1 << Pass::ALL.len())
+        .map(|mask: u32| {
+            Pass::ALL
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, pass)| *pass)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn unoptimized() {
+    for case in corpus() {
+        let output = brainfc::interpreter::run(&case.parse(), &case.input).unwrap();
+        case.check("unoptimized", &output);
+    }
+}
+
+#[test]
+fn optimizer_passes() {
+    for passes in pass_combinations() {
+        let optimizer = Optimizer::with_passes(&passes);
+        for case in corpus() {
+            let optimized = optimizer.optimize(case.parse());
+            let output = brainfc::interpreter::run(&optimized, &case.input).unwrap();
+            case.check(&format!("passes {passes:?}"), &output);
+        }
+    }
+}
+
+fn compiled(path: &str, options: CompileOptions) {
+    let dir = std::env::temp_dir().join(format!(
+        "brainfc-differential-{}-{}",
+        path.replace(' ', "-"),
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for case in corpus() {
+        let options = options.clone().output(dir.join(&case.name));
+        let output = run_compiled(case.parse(), &case.input, options);
+        case.check(path, &output);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Compiles the optimized `program` with `options`, runs it with `input` and
+/// returns what it printed
+fn run_compiled(program: Program, input: &[u8], options: CompileOptions) -> Vec<u8> {
+    let executable = brainfc::compile_to_executable(brainfc::optimize(program), &options).unwrap();
+
+    let mut child = Command::new(&executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{executable:?} exited with {}",
+        output.status
+    );
+    std::fs::remove_file(&executable).unwrap();
+    output.stdout
+}
+
+#[test]
+fn compiled_asm() {
+    compiled("Asm backend", CompileOptions::new().backend(Backend::Asm));
+}
+
+#[test]
+fn compiled_freestanding() {
+    let options = CompileOptions::new()
+        .backend(Backend::Asm)
+        .freestanding(true);
+    compiled("Asm backend freestanding", options);
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn compiled_llvm() {
+    compiled("Llvm backend", CompileOptions::new().backend(Backend::Llvm));
+}
+
+#[cfg(feature = "llvm")]
+#[test]
+fn compiled_llvm_freestanding() {
+    let options = CompileOptions::new()
+        .backend(Backend::Llvm)
+        .freestanding(true);
+    compiled("Llvm backend freestanding", options);
+}
+
+struct WasmIo {
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+/// Runs a module of `WasmAbi::Env` and returns what it printed
+fn run_wasm(wasm: &[u8], input: &[u8]) -> Result<Vec<u8>, wasmi::Error> {
+    let engine = wasmi::Engine::default();
+    let module = wasmi::Module::new(&engine, wasm).unwrap();
+    let io = WasmIo {
+        input: input.iter().rev().copied().collect(),
+        output: Vec::new(),
+    };
+    let mut store = wasmi::Store::new(&engine, io);
+
+    let mut linker = wasmi::Linker::new(&engine);
+    linker
+        .func_wrap("env", "read", |mut caller: wasmi::Caller<'_, WasmIo>| {
+            caller.data_mut().input.pop().map_or(-1, i32::from)
+        })
+        .unwrap()
+        .func_wrap(
+            "env",
+            "write",
+            |mut caller: wasmi::Caller<'_, WasmIo>, byte: i32| {
+                caller.data_mut().output.push(byte as u8)
+            },
+        )
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let run = instance.get_typed_func::<(), ()>(&store, "run").unwrap();
+    run.call(&mut store, ())?;
+    Ok(store.into_data().output)
+}
+
+fn compile_wasm(program: Program, name: &str) -> Vec<u8> {
+    let output = std::env::temp_dir().join(format!(
+        "brainfc-differential-{name}-{}",
+        std::process::id()
+    ));
+    let options = CompileOptions::new().backend(Backend::Wasm).output(&output);
+    let module = brainfc::compile_to_executable(program, &options).unwrap();
+    let wasm = std::fs::read(&module).unwrap();
+    std::fs::remove_file(&module).unwrap();
+    wasm
+}
+
+#[test]
+fn compiled_wasm() {
+    for case in corpus() {
+        let wasm = compile_wasm(brainfc::optimize(case.parse()), &case.name);
+        let output = run_wasm(&wasm, &case.input).unwrap();
+        case.check("Wasm backend", &output);
+    }
+}
+
+fn native_backends() -> Vec<Backend> {
+    vec![
+        #[cfg(feature = "llvm")]
+        Backend::Llvm,
+        Backend::Asm,
+    ]
+}
+
+/// `source` of `dialect`, expected to print what the brainfuck program
+/// `equivalent` prints
+fn dialect_case(name: &str, dialect: Dialect, source: &str, equivalent: &str) -> Case {
+    let equivalent = brainfc::parse(brainfc::lex(equivalent)).unwrap();
+    Case {
+        name: name.to_string(),
+        dialect,
+        source: source.to_string(),
+        input: Vec::new(),
+        expected: brainfc::interpreter::run(&equivalent, &[]).unwrap(),
+    }
+}
+
+/// Checks `case` in the interpreter, before and after optimizing, and
+/// compiled by the native backends
+fn check_dialect(case: &Case) {
+    let output = brainfc::interpreter::run(&case.parse(), &case.input).unwrap();
+    case.check("unoptimized", &output);
+    let optimized = brainfc::optimize(case.parse());
+    let output = brainfc::interpreter::run(&optimized, &case.input).unwrap();
+    case.check("optimized", &output);
+
+    let dir = std::env::temp_dir().join(format!(
+        "brainfc-differential-{}-{}",
+        case.name,
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    for backend in native_backends() {
+        let options = CompileOptions::new()
+            .backend(backend)
+            .output(dir.join(&case.name));
+        let output = run_compiled(case.parse(), &case.input, options);
+        case.check(&format!("{backend:?} backend"), &output);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cells_wrap_at_256() {
+    // 8 * 32 and 256 increments both leave 0, so neither loop prints 1
+    let source = format!(
+        "{}[>{}<-]>[[-]>+<]>.<{}[[-]>+<]>.-.",
+        "+".repeat(8),
+        "+".repeat(32),
+        "+".repeat(256)
+    );
+    let case = Case {
+        name: "wrap_at_256".to_string(),
+        dialect: Dialect::Brainfuck,
+        source,
+        input: Vec::new(),
+        expected: vec![0, 0, 255],
+    };
+    check_dialect(&case);
+    let wasm = compile_wasm(brainfc::optimize(case.parse()), &case.name);
+    case.check("Wasm backend", &run_wasm(&wasm, &[]).unwrap());
+}
+
+#[test]
+fn pbrain() {
+    // Procedure 0 prints the next letter, procedure 1 calls it twice.
+    // Procedure 5 is never defined, so calling it does nothing.
+    let source = "(>+.<) >>++++++++[<++++++++>-]<< :: +++++:----- : \
+                  +(-::+) : -(>-.<) +:";
+    let case = dialect_case(
+        "pbrain",
+        Dialect::Pbrain,
+        source,
+        ">>++++++++[<++++++++>-]<< >+.+.+.+.+.-.-.",
+    );
+    assert_eq!(case.expected, b"ABCDEDC");
+    check_dialect(&case);
+}
+
+/// The commands of the brainfuck program `source` spelled in `dialect`,
+/// with every space inside a token widened to a run of whitespace
+fn translate(source: &str, dialect: &Dialect) -> String {
+    let table = dialect.token_table().unwrap();
+    source
+        .chars()
+        .filter_map(|c| table.tokens().find(|(_, command)| *command == c))
+        .map(|(token, _)| token.replace(' ', "\n  "))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn token_dialects() {
+    let dir = std::env::temp_dir().join(format!("brainfc-dialects-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // `>` is a prefix of `<`, `-` spans two words
+    let mapping = dir.join("cow.toml");
+    std::fs::write(
+        &mapping,
+        r#"
+[tokens]
+">" = "moo"
+"<" = "mooo"
+"+" = "MOO"
+"-" = "MoO   mOo"
+"." = "OOM"
+"," = "oom"
+"[" = "moO"
+"]" = "Moo"
+"#,
+    )
+    .unwrap();
+    let custom = Dialect::load(&mapping).unwrap();
+
+    for (name, dialect) in [
+        ("ook", Dialect::Ook),
+        ("blub", Dialect::Blub),
+        ("cow", custom),
+    ] {
+        for case in corpus() {
+            let case = Case {
+                name: format!("{name}-{}", case.name),
+                source: translate(&case.source, &dialect),
+                dialect: dialect.clone(),
+                ..case
+            };
+            check_dialect(&case);
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Whether `output` is `a` and `b` interleaved, keeping the order of each
+fn interleaves(output: &[u8], a: &[u8], b: &[u8]) -> bool {
+    match output.split_first() {
+        None => a.is_empty() && b.is_empty(),
+        Some((byte, rest)) => {
+            (a.first() == Some(byte) && interleaves(rest, &a[1..], b))
+                || (b.first() == Some(byte) && interleaves(rest, a, &b[1..]))
+        }
+    }
+}
+
+#[test]
+fn brainfork() {
+    // Prints `A`, then forks: the parent prints `A` and 0, the child `B` and 1
+    let source = format!("{}.>{}Y<.>.", "+".repeat(65), "+".repeat(66));
+    // Each thread as brainfuck
+    let run = |source: &str| {
+        let program = brainfc::parse(brainfc::lex(source)).unwrap();
+        brainfc::interpreter::run(&program, &[]).unwrap()
+    };
+    let parent = run(&source.replace('Y', "[-]"));
+    let child = run(&source.replace('Y', ">+"));
+    assert_eq!(parent, b"AA\0");
+    assert_eq!(child, b"AB\x01");
+
+    // The interpreter runs the child first, then alternates
+    let case = Case {
+        name: "brainfork".to_string(),
+        dialect: Dialect::Brainfork,
+        source,
+        input: Vec::new(),
+        expected: b"ABA\x01\0".to_vec(),
+    };
+    let output = brainfc::interpreter::run(&case.parse(), &[]).unwrap();
+    case.check("unoptimized", &output);
+    let output = brainfc::interpreter::run(&brainfc::optimize(case.parse()), &[]).unwrap();
+    case.check("optimized", &output);
+
+    // Processes run in any order, but the output before the fork is written
+    // once and each thread prints in order
+    let dir = std::env::temp_dir().join(format!("brainfc-brainfork-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for backend in native_backends() {
+        let options = CompileOptions::new()
+            .backend(backend)
+            .output(dir.join("brainfork"));
+        let output = run_compiled(case.parse(), &case.input, options);
+        assert!(
+            output.first() == Some(&b'A') && interleaves(&output[1..], &parent[1..], &child[1..]),
+            "{backend:?} backend printed {output:?}"
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lang_example() {
+    // The example of the README
+    let source = r#"
+var n = 5;
+while n != 0 {
+    print '0' + n, " ";
+    n -= 1;
+}
+if n == 0 { print "liftoff\n"; } else { print "?\n"; }
+"#;
+    let dir = std::env::temp_dir().join(format!("brainfc-lang-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for backend in native_backends() {
+        let program = brainfc::lang::compile(source).unwrap();
+        let options = CompileOptions::new()
+            .backend(backend)
+            .output(dir.join("countdown"));
+        let output = run_compiled(program, &[], options);
+        assert_eq!(output, b"5 4 3 2 1 liftoff\n", "{backend:?} backend");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn inline_input() {
+    let source = ",.,.,+.!hi";
+    let (program, input) = brainfc::lexer::split_inline_input(source);
+    let input = input.unwrap();
+    let case = Case {
+        name: "inline_input".to_string(),
+        dialect: Dialect::Brainfuck,
+        source: program.to_string(),
+        input: Vec::new(),
+        expected: b"hi\0".to_vec(),
+    };
+    let output = brainfc::interpreter::run(&case.parse(), input.as_bytes()).unwrap();
+    case.check("unoptimized", &output);
+
+    // Whatever is on stdin is ignored
+    let dir = std::env::temp_dir().join(format!("brainfc-inline-input-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for backend in native_backends() {
+        for freestanding in [false, true] {
+            let options = CompileOptions::new()
+                .backend(backend)
+                .freestanding(freestanding)
+                .embedded_input(input)
+                .output(dir.join("inline"));
+            let output = run_compiled(case.parse(), b"xyz", options);
+            case.check(
+                &format!("{backend:?} backend, freestanding {freestanding}"),
+                &output,
+            );
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn debug_dump() {
+    // `#` dumps before the third cell is set, even after optimizing
+    let source = "+>++#>+++.";
+    let program = || {
+        let tokens = brainfc::lex_with(source, &Dialect::Brainfuck, &[Extension::DebugDump]);
+        brainfc::optimize(brainfc::parse(tokens).unwrap())
+    };
+
+    let dir = std::env::temp_dir().join(format!("brainfc-debug-dump-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for backend in native_backends() {
+        let options = CompileOptions::new()
+            .backend(backend)
+            .output(dir.join("dump"));
+        let executable = brainfc::compile_to_executable(program(), &options).unwrap();
+        let output = Command::new(&executable).output().unwrap();
+        assert!(output.status.success(), "{backend:?} backend");
+        assert_eq!(output.stdout, [3], "{backend:?} backend");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "tape pointer: 1\ncells 0..10: 1 2 0 0 0 0 0 0 0 0\n",
+            "{backend:?} backend"
+        );
+    }
+
+    // Dumping needs libc
+    let mut rejected = vec![CompileOptions::new().backend(Backend::Wasm)];
+    rejected.extend(
+        native_backends()
+            .into_iter()
+            .map(|backend| CompileOptions::new().backend(backend).freestanding(true)),
+    );
+    for options in rejected {
+        let options = options.output(dir.join("rejected"));
+        let err = brainfc::compile_to_executable(program(), &options).unwrap_err();
+        assert!(err.to_string().contains("`#` needs libc"), "{err}");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,2 @@
+Copies the text after the bang rather than stdin with inline input enabled
+,+[-.,+]!embedded in the source
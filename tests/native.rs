@@ -0,0 +1,310 @@
+//! Native code whose behavior goes beyond printing what the program prints:
+//! shared libraries built with `--crate-type cdylib` in every I/O mode,
+//! called from small C drivers compiled with `cc` that print what they
+//! observed, the report of `--profile` executables and how executables
+//! exit.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use brainfc::{Backend, CompileOptions, CrateType, ExitCode, IoMode};
+
+fn backends() -> Vec<Backend> {
+    vec![
+        #[cfg(feature = "llvm")]
+        Backend::Llvm,
+        Backend::Asm,
+    ]
+}
+
+fn temp_dir(name: &str, backend: Backend) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "brainfc-native-{name}-{backend:?}-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Builds `source` as `dir/libbf.so` next to its header `dir/libbf.h`
+fn library(dir: &Path, source: &str, options: CompileOptions) -> PathBuf {
+    let program = brainfc::parse(brainfc::lex(source)).unwrap();
+    let options = options
+        .crate_type(CrateType::Cdylib)
+        .output(dir.join("libbf"));
+    brainfc::compile_to_executable(program, &options).unwrap()
+}
+
+/// Compiles `driver` against the library in `dir` and returns its stdout
+fn run_driver(dir: &Path, driver: &str, stdin: &str) -> String {
+    let source = dir.join("driver.c");
+    std::fs::write(&source, driver).unwrap();
+    let executable = dir.join("driver");
+    let status = Command::new("cc")
+        .arg(&source)
+        .arg(format!("-I{}", dir.display()))
+        .arg(format!("-L{}", dir.display()))
+        .arg(format!("-Wl,-rpath,{}", dir.display()))
+        .args(["-lbf", "-o"])
+        .arg(&executable)
+        .status()
+        .unwrap();
+    assert!(status.success(), "could not compile the driver");
+
+    let mut child = Command::new(&executable)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "driver exited with {}",
+        output.status
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Builds `source` as the executable `dir/bf` and runs it
+fn run_executable(dir: &Path, source: &str, options: CompileOptions) -> Output {
+    let program = brainfc::parse(brainfc::lex(source)).unwrap();
+    let executable =
+        brainfc::compile_to_executable(program, &options.output(dir.join("bf"))).unwrap();
+    Command::new(&executable).output().unwrap()
+}
+
+#[test]
+fn cdylib_runs_on_fresh_and_caller_tapes() {
+    const DRIVER: &str = r#"
+#include <stdio.h>
+#include "libbf.h"
+
+int main(void) {
+    static uint8_t tape[BF_TAPE_SIZE];
+    uint8_t in[] = {3, 4}, out[8];
+    size_t out_len;
+
+    int ret = bf_run(in, 2, out, sizeof out, &out_len);
+    printf("%d %zu %d %d %d\n", ret, out_len, out[0], out[1], out[2]);
+
+    tape[1] = 10;
+    ret = bf_run_tape(tape, in, 1, out, sizeof out, &out_len);
+    printf("%d %zu %d %d %d | %d %d %d\n", ret, out_len, out[0], out[1], out[2],
+           tape[0], tape[1], tape[2]);
+    return 0;
+}
+"#;
+    // Adds the first input byte to the second cell, then prints the second
+    // input byte (255 on EOF), the sum and a third cell
+    let source = ",[->+<],.>.>+++.";
+
+    for backend in backends() {
+        let dir = temp_dir("tape", backend);
+        library(&dir, source, CompileOptions::new().backend(backend));
+        assert_eq!(
+            run_driver(&dir, DRIVER, ""),
+            "0 3 4 3 3\n0 3 255 13 3 | 255 13 3\n",
+            "{backend:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn buffer_output_is_truncated() {
+    const DRIVER: &str = r#"
+#include <stdio.h>
+#include "libbf.h"
+
+int main(void) {
+    uint8_t out[4] = {0};
+    size_t out_len;
+    int ret = bf_run(NULL, 0, out, 3, &out_len);
+    printf("%d %zu %d %d %d %d\n", ret, out_len, out[0], out[1], out[2], out[3]);
+    ret = bf_run(NULL, 0, out, 4, &out_len);
+    printf("%d %zu\n", ret, out_len);
+    return 0;
+}
+"#;
+    for backend in backends() {
+        let dir = temp_dir("truncated", backend);
+        library(&dir, "+.+.+.+.", CompileOptions::new().backend(backend));
+        assert_eq!(
+            run_driver(&dir, DRIVER, ""),
+            "1 3 1 2 3 0\n0 4\n",
+            "{backend:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn callback_io() {
+    const DRIVER: &str = r#"
+#include <stdio.h>
+#include "libbf.h"
+
+struct io { const char *in; int calls; };
+
+static int read_byte(void *ctx) {
+    struct io *io = ctx;
+    io->calls++;
+    return *io->in ? *io->in++ : -1;
+}
+
+static int write_byte(void *ctx, int c) {
+    struct io *io = ctx;
+    io->calls++;
+    return printf("%d ", c);
+}
+
+int main(void) {
+    struct io io = {"ab", 0};
+    int ret = bf_run(read_byte, write_byte, &io);
+    printf("| %d %d\n", ret, io.calls);
+    return 0;
+}
+"#;
+    for backend in backends() {
+        let dir = temp_dir("callback", backend);
+        let options = CompileOptions::new().backend(backend).io(IoMode::Callback);
+        library(&dir, ",+.,+.,+.", options);
+        assert_eq!(
+            run_driver(&dir, DRIVER, ""),
+            "98 99 0 | 0 6\n",
+            "{backend:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn stdio_io() {
+    const DRIVER: &str = r#"
+#include <stdio.h>
+#include "libbf.h"
+
+int main(void) {
+    int ret = bf_run();
+    fflush(stdout);
+    printf("| %d\n", ret);
+    return 0;
+}
+"#;
+    for backend in backends() {
+        let dir = temp_dir("stdio", backend);
+        let options = CompileOptions::new().backend(backend).io(IoMode::Stdio);
+        library(&dir, ",+.,+.,+.", options);
+        assert_eq!(run_driver(&dir, DRIVER, "ab"), "bc\0| 0\n", "{backend:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn inline_input_is_read_from_the_start_on_each_call() {
+    const DRIVER: &str = r#"
+#include <stdio.h>
+#include "libbf.h"
+
+int main(void) {
+    bf_run();
+    bf_run();
+    return 0;
+}
+"#;
+    for backend in backends() {
+        let dir = temp_dir("rerun", backend);
+        let options = CompileOptions::new()
+            .backend(backend)
+            .io(IoMode::Stdio)
+            .embedded_input("ab");
+        library(&dir, ",.,.,+.", options);
+        assert_eq!(run_driver(&dir, DRIVER, "xy"), "ab\0ab\0", "{backend:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn profile_counts_blocks_and_loops() {
+    let source = "+++[>++<-]>.";
+    for backend in backends() {
+        let dir = temp_dir("profile", backend);
+        let program = brainfc::parse(brainfc::lex(source)).unwrap();
+        let sites = brainfc::profile::sites(&program);
+        let options = CompileOptions::new()
+            .backend(backend)
+            .profile(true)
+            .output(dir.join("profile"));
+        let executable = brainfc::compile_to_executable(program, &options).unwrap();
+
+        let output = Command::new(&executable).output().unwrap();
+        assert!(output.status.success(), "{backend:?}");
+        assert_eq!(output.stdout, [6]);
+        let report = String::from_utf8(output.stderr).unwrap();
+
+        // `+++`, the loop, its body and `>.`
+        let counts = brainfc::profile::parse_report(&report);
+        assert_eq!(counts, [1, 3, 3, 1], "{backend:?}\n{report}");
+        assert_eq!(
+            brainfc::profile::executed_ops(&sites, &counts),
+            1 + 3 + 3 * 4 + 2
+        );
+        let hot_loops = report.lines().skip_while(|line| *line != "hot loops:");
+        assert!(
+            hot_loops
+                .skip(1)
+                .eq(["      1:4                 3 iterations"]),
+            "{backend:?}\n{report}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn exit_code_is_the_current_cell() {
+    for backend in backends() {
+        let dir = temp_dir("exit-code", backend);
+        let options = CompileOptions::new()
+            .backend(backend)
+            .exit_code(ExitCode::Cell);
+        let output = run_executable(&dir, "+++>++++++++[<+++++>-]<", options);
+        assert_eq!(output.status.code(), Some(43), "{backend:?}");
+
+        let output = run_executable(&dir, "+++>", CompileOptions::new().backend(backend));
+        assert_eq!(output.status.code(), Some(0), "{backend:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[test]
+fn tape_is_dumped_at_exit() {
+    // The window is cut off at the start of the tape
+    let cases = [
+        (
+            "+>++>>>>>>>>>>>+++<+.",
+            "tape pointer: 11\ncells 3..20: 0 0 0 0 0 0 0 0 1 3 0 0 0 0 0 0 0\n",
+        ),
+        (
+            "+>++.",
+            "tape pointer: 1\ncells 0..10: 1 2 0 0 0 0 0 0 0 0\n",
+        ),
+    ];
+    for backend in backends() {
+        let dir = temp_dir("dump-tape", backend);
+        for (source, dump) in cases {
+            let options = CompileOptions::new()
+                .backend(backend)
+                .dump_tape_at_exit(true);
+            let output = run_executable(&dir, source, options);
+            assert!(output.status.success(), "{backend:?}");
+            assert_eq!(output.stdout.len(), 1, "{backend:?}");
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                dump,
+                "{backend:?}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
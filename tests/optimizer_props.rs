@@ -0,0 +1,162 @@
+//! Property tests of the optimizer: random balanced programs have to print
+//! the same bytes and leave the same tape with and without
+//! `Optimizer::optimize`. proptest shrinks failing programs to a minimal one.
+
+use brainfc::interpreter::Interpreter;
+use brainfc::optimizer::Optimizer;
+use brainfc::parser::{Block, Program};
+use proptest::prelude::*;
+
+/// Programs running longer than this on the unoptimized path are skipped
+const MAX_STEPS: u64 = 20_000;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Outcome {
+    output: Vec<u8>,
+    /// Final tape and pointer, or `None` if the program failed
+    state: Option<(Vec<u8>, usize)>,
+}
+
+/// Runs `program` for at most `MAX_STEPS` steps, `None` if it does not finish
+fn run(program: &Program, input: &[u8]) -> Option<Outcome> {
+    let mut interpreter = Interpreter::new(program).with_input(input);
+    let result = loop {
+        if interpreter.steps() >= MAX_STEPS {
+            return None;
+        }
+        match interpreter.step() {
+            Ok(true) => {}
+            Ok(false) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+    Some(Outcome {
+        output: interpreter.output().to_vec(),
+        state: result
+            .ok()
+            .map(|_| (interpreter.tape().to_vec(), interpreter.pointer())),
+    })
+}
+
+fn command() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!["+", "-", ">", "<", ".", ","]).prop_map(String::from)
+}
+
+/// Offset of a cell a multiplication loop adds to: mostly a neighbour of the
+/// counter, sometimes so far away that the moves of the loop add up beyond
+/// what one `Move` holds
+fn target() -> impl Strategy<Value = i32> {
+    prop_oneof![100 => -3i32..=3, 1 => 10_000i32..=14_000]
+}
+
+/// Amount a multiplication loop adds to a cell: mostly small, sometimes
+/// beyond what one `Modify` holds, so that the lexer splits it
+fn amount() -> impl Strategy<Value = i32> {
+    prop_oneof![
+        100 => -3i32..=3,
+        1 => 32_000i32..=34_000,
+        1 => -34_000i32..=-32_000,
+    ]
+}
+
+/// A loop of the shape `MultiplyOpt` looks for: decrement the counter, add
+/// to cells around it, possibly to the same one several times, and move back
+fn multiply_loop() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec((target(), amount()), 1..6),
+        any::<bool>(),
+    )
+        .prop_map(|(targets, decrement_first)| {
+            let mut body = String::new();
+            let mut offset = 0;
+            for (target, value) in targets {
+                body += &moves(target - offset);
+                body += &modify(value);
+                offset = target;
+            }
+            body += &moves(-offset);
+            match decrement_first {
+                true => format!("[-{body}]"),
+                false => format!("[{body}-]"),
+            }
+        })
+}
+
+fn moves(diff: i32) -> String {
+    match diff >= 0 {
+        true => ">".repeat(diff as usize),
+        false => "<".repeat(-diff as usize),
+    }
+}
+
+fn modify(diff: i32) -> String {
+    match diff >= 0 {
+        true => "+".repeat(diff as usize),
+        false => "-".repeat(-diff as usize),
+    }
+}
+
+/// Commands, reset loops, multiplication loops and arbitrary nested loops
+fn code() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        4 => command(),
+        1 => Just("[-]".to_string()),
+        2 => multiply_loop(),
+    ];
+    let element = leaf.prop_recursive(4, 64, 8, |inner| {
+        prop::collection::vec(inner, 0..8).prop_map(|body| format!("[{}]", body.concat()))
+    });
+    // Start a few cells in, so that most programs do not run off the left end
+    prop::collection::vec(element, 0..24)
+        .prop_map(|elements| ">>>>".to_string() + &elements.concat())
+}
+
+/// Loops whose moves or modifications only fit i16 as separate tokens
+#[test]
+fn long_runs() {
+    let sources = [
+        // Leaves the tape, so only the unoptimized loop fails
+        (
+            format!("+[{}{}-]", ">".repeat(40_000), "<".repeat(40_000)),
+            false,
+        ),
+        (
+            format!(
+                "++[-{}{}{}+{}]",
+                ">".repeat(20_000),
+                "<".repeat(20_000),
+                ">".repeat(20_000),
+                "<".repeat(20_000)
+            ),
+            true,
+        ),
+        (format!("++[->{}<]>.", "+".repeat(40_000)), true),
+    ];
+    for (source, multiplies) in sources {
+        let parse = || brainfc::parse(brainfc::lex(&source)).unwrap();
+        let expected = run(&parse(), &[]).unwrap();
+        let optimized = Optimizer::new().optimize(parse());
+        let multiply = |block: &Block| matches!(block, Block::Multiply { .. });
+        assert_eq!(optimized.iter().any(multiply), multiplies);
+        assert_eq!(run(&optimized, &[]).unwrap(), expected);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(1024))]
+
+    #[test]
+    fn optimizer_preserves_behavior(
+        source in code(),
+        input in prop::collection::vec(any::<u8>(), 0..8),
+    ) {
+        let parse = || brainfc::parse(brainfc::lex(&source)).unwrap();
+        let expected = run(&parse(), &input);
+        prop_assume!(expected.is_some());
+
+        let optimized = Optimizer::new().optimize(parse());
+        let actual = run(&optimized, &input);
+        prop_assert!(actual.is_some(), "optimized program did not finish");
+        prop_assert_eq!(actual, expected);
+    }
+}